@@ -1,4 +1,3 @@
-use hound;
 use std::env;
 use std::fs::File;
 use trackermod::format::protracker::ProtrackerMod;
@@ -32,21 +31,18 @@ fn main() {
 
             loop {
                 let samples = player.get_samples().unwrap();
-                match samples {
-                    SampleOutput::I16(buf) => {
-                        if buf.len() == 0 {
-                            break;
-                        }
-                        for b in buf {
-                            writer.write_sample(*b).unwrap();
-                        }
+                if let SampleOutput::I16(buf) = samples {
+                    if buf.is_empty() {
+                        break;
+                    }
+                    for b in buf {
+                        writer.write_sample(*b).unwrap();
                     }
-                    _ => {}
                 }
             }
         }
         Err(e) => {
-            print!("Error: {}\n", e);
+            println!("Error: {}", e);
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::env;
 use std::fs::File;
-use trackermod::format::protracker::ProtrackerMod;
+use trackermod::format::protracker::{LoadOptions, ProtrackerMod};
 
 fn main() {
     let mut x = env::args();
@@ -8,12 +8,19 @@ fn main() {
     let filename = x.next().unwrap();
 
     let mut f = File::open(filename).unwrap();
-    match ProtrackerMod::deserialize(&mut f) {
-        Ok(mod_data) => {
+    let options = LoadOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    match ProtrackerMod::deserialize_with_options(&mut f, &options) {
+        Ok((mod_data, warnings)) => {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
             print!("{}", mod_data.info_str());
         }
         Err(e) => {
-            print!("Error: {}\n", e);
+            println!("Error: {}", e);
         }
     }
 }
@@ -0,0 +1,116 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use trackermod::format::protracker::{
+    ChannelData, Division, Effect, Limits, LoadOptions, Pattern, ProtrackerMod, Sample,
+};
+use trackermod::player::protracker::{ClockFreq, ProtrackerPlayer};
+use trackermod::player::{OutputFormat, SampleFormat, SampleOutput};
+
+const SAMPLE_RATE: u32 = 8000;
+const SECONDS: usize = 3;
+
+fuzz_target!(|data: &[u8]| {
+    load_and_play(data);
+    // random bytes rarely get past the header checks, so the input is also
+    // used to mutate a valid module
+    load_and_play(&mutated_mod(data));
+});
+
+fn load_and_play(data: &[u8]) {
+    for lenient in [false, true].iter() {
+        let options = LoadOptions {
+            lenient: *lenient,
+            limits: Limits {
+                max_file_size: Some(1 << 22),
+                max_sample_bytes: Some(1 << 22),
+                max_patterns: Some(128),
+                max_channels: Some(32),
+            },
+        };
+        let load = || ProtrackerMod::deserialize_with_options(&mut Cursor::new(data), &options);
+
+        if let Ok((pt_mod, _)) = load() {
+            pt_mod.info_str();
+        }
+
+        if let Ok((borrowed, _)) = ProtrackerMod::from_bytes_borrowed(data, &options) {
+            borrowed.into_owned();
+        }
+
+        if let Ok((pt_mod, _)) = load() {
+            let output_format = OutputFormat {
+                sample_rate: SAMPLE_RATE,
+                sample_format: SampleFormat::F32,
+                channel_count: 2,
+            };
+            if let Ok(mut player) = ProtrackerPlayer::new(pt_mod, ClockFreq::Pal, output_format) {
+                let mut rendered = 0;
+                while rendered < SAMPLE_RATE as usize * SECONDS {
+                    match player.get_samples() {
+                        Ok(SampleOutput::F32(buf)) if !buf.is_empty() => rendered += buf.len() / 2,
+                        _ => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 4 channel module with a looped square wave and every effect in its first
+// pattern, changed by `data` read as (position, byte) triples; a remaining
+// byte truncates the module
+fn mutated_mod(data: &[u8]) -> Vec<u8> {
+    let square = Sample {
+        name: String::from("square"),
+        finetune: 0,
+        length: 64,
+        volume: 64,
+        repeat_offset: 0,
+        repeat_length: 64,
+        data: [vec![64; 32], vec![-64; 32]].concat(),
+    };
+    let patterns = (0..2)
+        .map(|_| Pattern {
+            divisions: (0..64)
+                .map(|idx| Division {
+                    channel_data: (0..4)
+                        .map(|channel| {
+                            let (sample, period) =
+                                if idx % 4 == channel { (1, 214) } else { (0, 0) };
+                            ChannelData {
+                                sample,
+                                period,
+                                effect: Effect::from_nibbles((idx % 16) as u8, 1, 2),
+                            }
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+    let mut pattern_table = [0; 128];
+    pattern_table[1] = 1;
+    let pt_mod = ProtrackerMod {
+        title: String::from("fuzz"),
+        num_channels: 4,
+        samples: vec![square],
+        song_length: 2,
+        restart_pos: 127,
+        pattern_table,
+        patterns,
+    };
+    let mut module = vec![];
+    pt_mod.serialize(&mut module).unwrap();
+
+    let mut triples = data.chunks_exact(3);
+    for triple in &mut triples {
+        let pos = u16::from_be_bytes([triple[0], triple[1]]) as usize % module.len();
+        module[pos] = triple[2];
+    }
+    if let Some(len) = triples.remainder().first() {
+        let len = *len as usize * module.len() / 256;
+        module.truncate(len);
+    }
+    module
+}
@@ -0,0 +1,278 @@
+use super::{Effect, EffectType, EffectTypeExtended};
+
+/// Decoded effect command, with parameters in their semantic form.
+///
+/// Converts losslessly to and from the raw `Effect`. Encodings which have
+/// no canonical decoded form (a pattern break row which is not valid BCD)
+/// are kept as `Raw`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    None,
+    Arpeggio {
+        x: u8,
+        y: u8,
+    },
+    PortaUp(u8),
+    PortaDown(u8),
+    TonePorta(u8),
+    Vibrato {
+        speed: u8,
+        depth: u8,
+    },
+    TonePortaVolumeSlide {
+        up: u8,
+        down: u8,
+    },
+    VibratoVolumeSlide {
+        up: u8,
+        down: u8,
+    },
+    Tremolo {
+        speed: u8,
+        depth: u8,
+    },
+    SetPanning(u8),
+    SampleOffset(u8),
+    VolumeSlide {
+        up: u8,
+        down: u8,
+    },
+    PositionJump(u8),
+    SetVolume(u8),
+    /// Row decoded from the BCD parameter (x * 10 + y)
+    PatternBreak {
+        row: u8,
+    },
+    /// Ticks per division, parameter 0x00..0x1f
+    SetSpeed(u8),
+    /// Beats per minute, parameter 0x20..0xff
+    SetTempo(u8),
+    SetFilter(u8),
+    FinePortaUp(u8),
+    FinePortaDown(u8),
+    Glissando(u8),
+    SetVibratoWaveform(u8),
+    SetFinetune(i8),
+    PatternLoop(u8),
+    SetTremoloWaveform(u8),
+    SetPanningCoarse(u8),
+    Retrigger(u8),
+    FineVolumeSlideUp(u8),
+    FineVolumeSlideDown(u8),
+    NoteCut(u8),
+    NoteDelay(u8),
+    PatternDelay(u8),
+    InvertLoop(u8),
+    Raw(Effect),
+}
+
+impl Command {
+    /// Decode an effect with Ultimate SoundTracker meanings: 1xy is
+    /// arpeggio, 2xy slides up by y or, if y is 0, down by x.
+    pub fn from_soundtracker(effect: Effect) -> Command {
+        match effect {
+            Effect::Normal {
+                effect_type: EffectType::Arpeggio,
+                ..
+            } => Command::None,
+            Effect::Normal {
+                effect_type: EffectType::SlideUp,
+                param1: x,
+                param2: y,
+            } => {
+                if x == 0 && y == 0 {
+                    Command::None
+                } else {
+                    Command::Arpeggio { x, y }
+                }
+            }
+            Effect::Normal {
+                effect_type: EffectType::SlideDown,
+                param1: x,
+                param2: y,
+            } => {
+                if y > 0 {
+                    Command::PortaUp(y)
+                } else if x > 0 {
+                    Command::PortaDown(x)
+                } else {
+                    Command::None
+                }
+            }
+            _ => Command::from(effect),
+        }
+    }
+}
+
+impl From<Effect> for Command {
+    fn from(effect: Effect) -> Command {
+        match effect {
+            Effect::Normal {
+                effect_type,
+                param1: x,
+                param2: y,
+            } => {
+                let xy = (x << 4) | y;
+                match effect_type {
+                    EffectType::Arpeggio => {
+                        if xy == 0 {
+                            Command::None
+                        } else {
+                            Command::Arpeggio { x, y }
+                        }
+                    }
+                    EffectType::SlideUp => Command::PortaUp(xy),
+                    EffectType::SlideDown => Command::PortaDown(xy),
+                    EffectType::SlideToNote => Command::TonePorta(xy),
+                    EffectType::Vibrato => Command::Vibrato { speed: x, depth: y },
+                    EffectType::SlideToNoteVolumeSlide => {
+                        Command::TonePortaVolumeSlide { up: x, down: y }
+                    }
+                    EffectType::VibratoVolumeSlide => {
+                        Command::VibratoVolumeSlide { up: x, down: y }
+                    }
+                    EffectType::Tremolo => Command::Tremolo { speed: x, depth: y },
+                    EffectType::SetPanningPosition => Command::SetPanning(xy),
+                    EffectType::SetSampleOffset => Command::SampleOffset(xy),
+                    EffectType::VolumeSlide => Command::VolumeSlide { up: x, down: y },
+                    EffectType::PositionJump => Command::PositionJump(xy),
+                    EffectType::SetVolume => Command::SetVolume(xy),
+                    EffectType::PatternBreak => {
+                        if x <= 9 && y <= 9 {
+                            Command::PatternBreak { row: x * 10 + y }
+                        } else {
+                            Command::Raw(effect)
+                        }
+                    }
+                    EffectType::Extended => Command::Raw(effect),
+                    EffectType::SetSpeed => {
+                        if xy < 0x20 {
+                            Command::SetSpeed(xy)
+                        } else {
+                            Command::SetTempo(xy)
+                        }
+                    }
+                }
+            }
+            Effect::Extended { effect_type, param } => match effect_type {
+                EffectTypeExtended::SetFilterOnOff => Command::SetFilter(param),
+                EffectTypeExtended::FineslideUp => Command::FinePortaUp(param),
+                EffectTypeExtended::FineslideDown => Command::FinePortaDown(param),
+                EffectTypeExtended::Glissando => Command::Glissando(param),
+                EffectTypeExtended::SetVibratoWaveform => Command::SetVibratoWaveform(param),
+                EffectTypeExtended::SetFinetuneValue => {
+                    Command::SetFinetune(super::signed_nibble(param))
+                }
+                EffectTypeExtended::LoopPattern => Command::PatternLoop(param),
+                EffectTypeExtended::SetTremoloWaveform => Command::SetTremoloWaveform(param),
+                EffectTypeExtended::Unused => Command::SetPanningCoarse(param),
+                EffectTypeExtended::RetriggerSample => Command::Retrigger(param),
+                EffectTypeExtended::FineVolumeSlideUp => Command::FineVolumeSlideUp(param),
+                EffectTypeExtended::FineVolumeSlideDown => Command::FineVolumeSlideDown(param),
+                EffectTypeExtended::CutSample => Command::NoteCut(param),
+                EffectTypeExtended::DelaySample => Command::NoteDelay(param),
+                EffectTypeExtended::DelayPattern => Command::PatternDelay(param),
+                EffectTypeExtended::InvertLoop => Command::InvertLoop(param),
+            },
+        }
+    }
+}
+
+impl From<Command> for Effect {
+    fn from(command: Command) -> Effect {
+        let normal = |effect_type, x: u8, y: u8| Effect::Normal {
+            effect_type,
+            param1: x & 0x0f,
+            param2: y & 0x0f,
+        };
+        let normal_xy = |effect_type, xy: u8| normal(effect_type, xy >> 4, xy);
+        let extended = |effect_type, param: u8| Effect::Extended {
+            effect_type,
+            param: param & 0x0f,
+        };
+
+        match command {
+            Command::None => normal(EffectType::Arpeggio, 0, 0),
+            Command::Arpeggio { x, y } => normal(EffectType::Arpeggio, x, y),
+            Command::PortaUp(xy) => normal_xy(EffectType::SlideUp, xy),
+            Command::PortaDown(xy) => normal_xy(EffectType::SlideDown, xy),
+            Command::TonePorta(xy) => normal_xy(EffectType::SlideToNote, xy),
+            Command::Vibrato { speed, depth } => normal(EffectType::Vibrato, speed, depth),
+            Command::TonePortaVolumeSlide { up, down } => {
+                normal(EffectType::SlideToNoteVolumeSlide, up, down)
+            }
+            Command::VibratoVolumeSlide { up, down } => {
+                normal(EffectType::VibratoVolumeSlide, up, down)
+            }
+            Command::Tremolo { speed, depth } => normal(EffectType::Tremolo, speed, depth),
+            Command::SetPanning(xy) => normal_xy(EffectType::SetPanningPosition, xy),
+            Command::SampleOffset(xy) => normal_xy(EffectType::SetSampleOffset, xy),
+            Command::VolumeSlide { up, down } => normal(EffectType::VolumeSlide, up, down),
+            Command::PositionJump(xy) => normal_xy(EffectType::PositionJump, xy),
+            Command::SetVolume(xy) => normal_xy(EffectType::SetVolume, xy),
+            Command::PatternBreak { row } => normal(EffectType::PatternBreak, row / 10, row % 10),
+            Command::SetSpeed(xy) | Command::SetTempo(xy) => normal_xy(EffectType::SetSpeed, xy),
+            Command::SetFilter(x) => extended(EffectTypeExtended::SetFilterOnOff, x),
+            Command::FinePortaUp(x) => extended(EffectTypeExtended::FineslideUp, x),
+            Command::FinePortaDown(x) => extended(EffectTypeExtended::FineslideDown, x),
+            Command::Glissando(x) => extended(EffectTypeExtended::Glissando, x),
+            Command::SetVibratoWaveform(x) => extended(EffectTypeExtended::SetVibratoWaveform, x),
+            Command::SetFinetune(x) => extended(EffectTypeExtended::SetFinetuneValue, x as u8),
+            Command::PatternLoop(x) => extended(EffectTypeExtended::LoopPattern, x),
+            Command::SetTremoloWaveform(x) => extended(EffectTypeExtended::SetTremoloWaveform, x),
+            Command::SetPanningCoarse(x) => extended(EffectTypeExtended::Unused, x),
+            Command::Retrigger(x) => extended(EffectTypeExtended::RetriggerSample, x),
+            Command::FineVolumeSlideUp(x) => extended(EffectTypeExtended::FineVolumeSlideUp, x),
+            Command::FineVolumeSlideDown(x) => extended(EffectTypeExtended::FineVolumeSlideDown, x),
+            Command::NoteCut(x) => extended(EffectTypeExtended::CutSample, x),
+            Command::NoteDelay(x) => extended(EffectTypeExtended::DelaySample, x),
+            Command::PatternDelay(x) => extended(EffectTypeExtended::DelayPattern, x),
+            Command::InvertLoop(x) => extended(EffectTypeExtended::InvertLoop, x),
+            Command::Raw(effect) => effect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for txy in 0..0x1000u16 {
+            let (t, x, y) = ((txy >> 8) as u8, (txy >> 4) as u8 & 0x0f, txy as u8 & 0x0f);
+            let effect = Effect::from_nibbles(t, x, y);
+            let command = Command::from(effect);
+            assert_eq!(effect, Effect::from(command));
+            if t != 0xd {
+                assert!(!matches!(command, Command::Raw(_)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_soundtracker() {
+        let decode = |t, x, y| Command::from_soundtracker(Effect::from_nibbles(t, x, y));
+        assert_eq!(Command::None, decode(0x0, 3, 7));
+        assert_eq!(Command::Arpeggio { x: 3, y: 7 }, decode(0x1, 3, 7));
+        assert_eq!(Command::None, decode(0x1, 0, 0));
+        assert_eq!(Command::PortaUp(3), decode(0x2, 0, 3));
+        assert_eq!(Command::PortaDown(5), decode(0x2, 5, 0));
+        assert_eq!(Command::SetVolume(0x20), decode(0xc, 2, 0));
+    }
+
+    #[test]
+    fn test_decode() {
+        let decode = |t, x, y| Command::from(Effect::from_nibbles(t, x, y));
+        assert_eq!(Command::None, decode(0x0, 0, 0));
+        assert_eq!(Command::Arpeggio { x: 3, y: 7 }, decode(0x0, 3, 7));
+        assert_eq!(Command::PortaUp(0x12), decode(0x1, 1, 2));
+        assert_eq!(Command::VolumeSlide { up: 0, down: 4 }, decode(0xa, 0, 4));
+        assert_eq!(Command::PatternBreak { row: 32 }, decode(0xd, 3, 2));
+        assert_eq!(Command::SetSpeed(0x1f), decode(0xf, 1, 0xf));
+        assert_eq!(Command::SetTempo(0x20), decode(0xf, 2, 0));
+        assert_eq!(Command::SetFinetune(-1), decode(0xe, 5, 0xf));
+        assert_eq!(Command::NoteDelay(3), decode(0xe, 0xd, 3));
+        assert!(matches!(decode(0xd, 1, 0xa), Command::Raw(_)));
+    }
+}
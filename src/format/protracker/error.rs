@@ -0,0 +1,200 @@
+use std::fmt;
+
+/// Part of the module file in which a parse error occurred.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Section {
+    Title,
+    SampleHeaders,
+    PatternTable,
+    Tag,
+    Patterns,
+    SampleData,
+}
+
+/// Resource limit from `Limits` which a module exceeded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Limit {
+    FileSize,
+    SampleBytes,
+    Patterns,
+    Channels,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The file ended inside `section`, `offset` bytes into the file.
+    Truncated {
+        section: Section,
+        offset: u64,
+    },
+    /// The file has no known format tag and does not look like a 15-sample module.
+    UnknownFormat,
+    /// The song length in the header is 0.
+    EmptyOrderList,
+    /// The song length in the header is larger than the 128-entry pattern table.
+    SongLengthOutOfRange {
+        length: u8,
+    },
+    /// A pattern table entry refers to a pattern beyond what the format can store.
+    PatternIndexOutOfRange {
+        position: u8,
+        pattern: u8,
+        num_patterns: usize,
+    },
+    /// The sample header declares more data than the file contains.
+    SampleTooLong {
+        sample: u8,
+        length: u32,
+        available: u32,
+    },
+    /// The module needs more resources than allowed by the load options.
+    LimitExceeded {
+        limit: Limit,
+        value: u64,
+        max: u64,
+    },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Section::Title => "title",
+            Section::SampleHeaders => "sample headers",
+            Section::PatternTable => "pattern table",
+            Section::Tag => "format tag",
+            Section::Patterns => "patterns",
+            Section::SampleData => "sample data",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Limit::FileSize => "file size",
+            Limit::SampleBytes => "sample data size",
+            Limit::Patterns => "pattern count",
+            Limit::Channels => "channel count",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated { section, offset } => {
+                write!(f, "file truncated in {} at offset {}", section, offset)
+            }
+            ParseError::UnknownFormat => write!(f, "unknown module format"),
+            ParseError::EmptyOrderList => write!(f, "song length is 0"),
+            ParseError::SongLengthOutOfRange { length } => {
+                write!(f, "song length {} exceeds 128", length)
+            }
+            ParseError::PatternIndexOutOfRange {
+                position,
+                pattern,
+                num_patterns,
+            } => write!(
+                f,
+                "pattern {} at position {} out of range (max. {} patterns)",
+                pattern, position, num_patterns
+            ),
+            ParseError::SampleTooLong {
+                sample,
+                length,
+                available,
+            } => write!(
+                f,
+                "sample {:02x} declares {} bytes, only {} available",
+                sample, length, available
+            ),
+            ParseError::LimitExceeded { limit, value, max } => {
+                write!(f, "{} {} exceeds limit of {}", limit, value, max)
+            }
+            ParseError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(e: std::io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+/// Repair applied to a damaged module when loading in lenient mode.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LoadWarning {
+    /// Song length of 0, replaced by a sequence playing pattern 0 once.
+    EmptyOrderList,
+    /// Song length larger than 128, clamped to 128.
+    SongLengthClamped { length: u8 },
+    /// Pattern table entry out of range, replaced by pattern 0.
+    PatternIndexReplaced { position: u8, pattern: u8 },
+    /// Pattern data cut short, missing divisions are left empty.
+    PatternDataTruncated { length: u64, available: u64 },
+    /// Sample data cut short, missing data is filled with silence.
+    SampleDataTruncated {
+        sample: u8,
+        length: u32,
+        available: u32,
+    },
+    /// Sample loop extending past the end of the sample, clamped to the
+    /// sample length (or disabled if it starts past the end).
+    LoopClamped {
+        sample: u8,
+        repeat_offset: u32,
+        repeat_length: u32,
+    },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadWarning::EmptyOrderList => write!(f, "song length is 0, playing pattern 0"),
+            LoadWarning::SongLengthClamped { length } => {
+                write!(f, "song length {} clamped to 128", length)
+            }
+            LoadWarning::PatternIndexReplaced { position, pattern } => write!(
+                f,
+                "pattern {} at position {} out of range, replaced by pattern 0",
+                pattern, position
+            ),
+            LoadWarning::PatternDataTruncated { length, available } => write!(
+                f,
+                "pattern data truncated ({} of {} bytes), filled with empty divisions",
+                available, length
+            ),
+            LoadWarning::SampleDataTruncated {
+                sample,
+                length,
+                available,
+            } => write!(
+                f,
+                "sample {:02x} truncated ({} of {} bytes), filled with silence",
+                sample, available, length
+            ),
+            LoadWarning::LoopClamped {
+                sample,
+                repeat_offset,
+                repeat_length,
+            } => write!(
+                f,
+                "sample {:02x} loop {}+{} past sample end, clamped",
+                sample, repeat_offset, repeat_length
+            ),
+        }
+    }
+}
@@ -11,16 +11,23 @@ pub fn info_mod(pt_mod: &ProtrackerMod) -> String {
     // sequence
 
     ret.push_str("\n\nSequence:\n");
-    ret.push_str(&info_sequence(&pt_mod.sequence));
+    ret.push_str(&info_sequence(pt_mod.sequence()));
 
     // patterns
 
     ret.push_str("\n\n");
 
+    // Ultimate SoundTracker effects are shown as the ProTracker effects
+    // they play as
+    let decode: fn(Effect) -> Command = if pt_mod.has_soundtracker_effects() {
+        Command::from_soundtracker
+    } else {
+        Command::from
+    };
     for (idx, pattern) in pt_mod.patterns.iter().enumerate() {
         ret.push_str(&format!("Pattern {:>02x}:\n", idx));
-        ret.push_str(&info_pattern(pattern, &pt_mod.samples));
-        ret.push_str("\n");
+        ret.push_str(&info_pattern(pattern, &pt_mod.samples, decode));
+        ret.push('\n');
     }
 
     ret
@@ -69,54 +76,44 @@ fn info_sequence(sequence: &[u8]) -> String {
     row_strs.join("\n")
 }
 
-fn info_pattern(pattern: &Pattern, samples: &[Sample]) -> String {
+fn info_pattern(pattern: &Pattern, samples: &[Sample], decode: fn(Effect) -> Command) -> String {
     let mut ret = String::new();
     for (idx, division) in pattern.divisions.iter().enumerate() {
         ret.push_str(&format!("{:>02x}      ", idx));
         let channel_strs: Vec<_> = division
             .channel_data
             .iter()
-            .map(|channel| info_channel(channel, samples))
+            .map(|channel| info_channel(channel, samples, decode))
             .collect();
         ret.push_str(&channel_strs.join("      "));
-        ret.push_str("\n");
+        ret.push('\n');
     }
     ret
 }
 
-fn info_channel(channel: &ChannelData, samples: &[Sample]) -> String {
+fn info_channel(
+    channel: &ChannelData,
+    samples: &[Sample],
+    decode: fn(Effect) -> Command,
+) -> String {
     if channel.sample > 0 {
-        let note_str = format!(
-            "{}",
-            note::get_note(
-                samples[channel.sample as usize - 1].finetune,
-                channel.period,
-            )
-        );
+        let finetune = samples
+            .get(channel.sample as usize - 1)
+            .map_or(0, |sample| sample.finetune);
+        let note_str = note::get_note(finetune, channel.period)
+            .map_or_else(|| String::from("---"), |note| format!("{}", note));
         format!(
             "{:>02x}|{}|{}",
             channel.sample,
             note_str,
-            info_effect(&channel.effect)
+            info_effect(decode(channel.effect))
         )
     } else {
         String::from("..........")
     }
 }
 
-fn info_effect(effect: &Effect) -> String {
-    match effect {
-        Effect::Normal {
-            effect_type,
-            param1,
-            param2,
-        } => {
-            let t: u8 = (*effect_type).into();
-            format!("{:1x}{:1x}{:1x}", t, param1, param2)
-        }
-        Effect::Extended { effect_type, param } => {
-            let t: u8 = (*effect_type).into();
-            format!("e{:1x}{:1x}", t, param)
-        }
-    }
+fn info_effect(command: Command) -> String {
+    let (t, x, y) = Effect::from(command).to_nibbles();
+    format!("{:1x}{:1x}{:1x}", t, x, y)
 }
@@ -1,16 +1,32 @@
 use byteorder::{BigEndian, ReadBytesExt};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use std::convert::{From, TryFrom};
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::str::from_utf8;
 
+mod command;
+mod error;
 mod info;
 mod note;
+mod options;
+mod serialize;
+
+pub use command::Command;
+pub use error::{Limit, LoadWarning, ParseError, Section};
+pub use options::{Limits, LoadOptions};
+
+pub(crate) use note::{arpeggio_period, note_period, tone_porta_period};
 
 pub struct ProtrackerMod {
     pub title: String,
+    pub num_channels: u8,
     pub samples: Vec<Sample>,
-    pub sequence: Vec<u8>,
+    pub song_length: u8,
+    /// Restart position (NoiseTracker) or initial tempo (Ultimate SoundTracker);
+    /// ProTracker writes 127 here
+    pub restart_pos: u8,
+    /// All 128 entries of the pattern table, including those past the song end
+    pub pattern_table: [u8; 128],
     pub patterns: Vec<Pattern>,
 }
 
@@ -38,6 +54,7 @@ pub struct ChannelData {
     pub effect: Effect,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Effect {
     Normal {
         effect_type: EffectType,
@@ -92,79 +109,614 @@ pub enum EffectTypeExtended {
     InvertLoop = 0xf,
 }
 
+impl Effect {
+    /// Decode an effect from its three nibbles as stored in pattern data
+    /// (effect type, parameter x, parameter y).
+    pub fn from_nibbles(t: u8, x: u8, y: u8) -> Effect {
+        let (t, x, y) = (t & 0x0f, x & 0x0f, y & 0x0f);
+        let effect_type = EffectType::try_from(t).unwrap();
+        if effect_type == EffectType::Extended {
+            // for E-commands, x selects the sub-command
+            Effect::Extended {
+                effect_type: EffectTypeExtended::try_from(x).unwrap(),
+                param: y,
+            }
+        } else {
+            Effect::Normal {
+                effect_type,
+                param1: x,
+                param2: y,
+            }
+        }
+    }
+
+    /// Encode the effect into its three nibbles (effect type, x, y).
+    pub fn to_nibbles(&self) -> (u8, u8, u8) {
+        match self {
+            Effect::Normal {
+                effect_type,
+                param1,
+                param2,
+            } => ((*effect_type).into(), param1 & 0x0f, param2 & 0x0f),
+            Effect::Extended { effect_type, param } => (
+                EffectType::Extended.into(),
+                (*effect_type).into(),
+                param & 0x0f,
+            ),
+        }
+    }
+}
+
 impl ProtrackerMod {
-    pub fn deserialize<R>(mut r: &mut R) -> std::io::Result<ProtrackerMod>
+    pub fn deserialize<R>(r: &mut R) -> Result<ProtrackerMod, ParseError>
     where
         R: Read + Seek,
     {
-        // check format
-        // seek to 1080 = 20 + 31 * (22 + 2 + 1 + 1 + 2 + 2) + 1 + 1 + 128
-        r.seek(SeekFrom::Start(1080))?;
-
-        // check for file type (num samples / channels)
-        let tag = parse_str(&mut r, 4)?;
-        let (num_samples, num_channels, max_pattern) = match tag.as_ref() {
-            "M.K." | "FLT4" | "4CHN" => (31u8, 4u8, 63u8),
-            "M!K!" => (31u8, 4u8, 255u8),
-            "6CHN" => (31u8, 4u8, 63u8),
-            "FLT8" | "8CHN" => (31u8, 4u8, 63u8),
-            _ => (15u8, 4u8, 63u8),
-        };
+        let (pt_mod, _) = ProtrackerMod::deserialize_with_options(r, &LoadOptions::default())?;
+        Ok(pt_mod)
+    }
+
+    pub fn deserialize_with_options<R>(
+        r: &mut R,
+        options: &LoadOptions,
+    ) -> Result<(ProtrackerMod, Vec<LoadWarning>), ParseError>
+    where
+        R: Read + Seek,
+    {
+        let file_size = r.seek(SeekFrom::End(0))?;
+        check_limit(Limit::FileSize, file_size, options.limits.max_file_size)?;
 
         // go back to start of file
         r.seek(SeekFrom::Start(0))?;
+        read_mod(r, Some(file_size), options)
+    }
+
+    /// Parse a module from a non-seekable stream.
+    pub fn from_reader<R>(r: &mut R) -> Result<ProtrackerMod, ParseError>
+    where
+        R: Read,
+    {
+        let (pt_mod, _) = ProtrackerMod::from_reader_with_options(r, &LoadOptions::default())?;
+        Ok(pt_mod)
+    }
+
+    pub fn from_reader_with_options<R>(
+        r: &mut R,
+        options: &LoadOptions,
+    ) -> Result<(ProtrackerMod, Vec<LoadWarning>), ParseError>
+    where
+        R: Read,
+    {
+        read_mod(r, None, options)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<ProtrackerMod, ParseError> {
+        let (borrowed, _) = ProtrackerMod::from_bytes_borrowed(data, &LoadOptions::default())?;
+        Ok(borrowed.into_owned())
+    }
+
+    /// Parse a module from a byte slice without copying the sample data,
+    /// which is referenced from `data` instead.
+    pub fn from_bytes_borrowed<'a>(
+        data: &'a [u8],
+        options: &LoadOptions,
+    ) -> Result<(BorrowedMod<'a>, Vec<LoadWarning>), ParseError> {
+        let mut warnings = vec![];
+
+        check_limit(
+            Limit::FileSize,
+            data.len() as u64,
+            options.limits.max_file_size,
+        )?;
+        // detect from the header only, as for streams: the 15-sample
+        // checks must not run over the pattern and sample data
+        let format = FormatInfo::detect(&data[..data.len().min(HEADER_SIZE as usize)])?;
+
+        let mut r = OffsetReader {
+            inner: &mut &data[..],
+            offset: 0,
+        };
+        let mut pt_mod = parse_headers(
+            &mut r,
+            &format,
+            Some(data.len() as u64),
+            options,
+            &mut warnings,
+        )?;
+
+        let mut remaining = &data[r.offset as usize..];
+        let mut sample_data = vec![];
+        for (idx, sample) in pt_mod.samples.iter().enumerate() {
+            let n = (sample.length as usize).min(remaining.len());
+            check_sample_length(idx, sample, n, options, &mut warnings)?;
+            let (head, tail) = remaining.split_at(n);
+            sample_data.push(as_i8_slice(head));
+            remaining = tail;
+        }
+        if options.lenient {
+            clamp_loops(&mut pt_mod.samples, &mut warnings);
+        }
+        Ok((
+            BorrowedMod {
+                pt_mod,
+                sample_data,
+            },
+            warnings,
+        ))
+    }
+
+    /// Pattern table entries played in the song
+    pub fn sequence(&self) -> &[u8] {
+        &self.pattern_table[..(self.song_length as usize).min(128)]
+    }
+
+    /// 15-sample module (Ultimate SoundTracker and compatibles)
+    pub fn is_soundtracker(&self) -> bool {
+        self.samples.len() == 15
+    }
+
+    /// 15-sample module using Ultimate SoundTracker effect meanings (see
+    /// `Command::from_soundtracker`). Later SoundTracker versions use
+    /// ProTracker's 0xy arpeggio and effects 3..B, which UST does not know.
+    pub fn has_soundtracker_effects(&self) -> bool {
+        self.is_soundtracker()
+            && self.patterns.iter().all(|pattern| {
+                pattern.divisions.iter().all(|division| {
+                    division.channel_data.iter().all(|cd| {
+                        let (t, x, y) = cd.effect.to_nibbles();
+                        !(t == 0 && (x, y) != (0, 0) || (0x3..=0xb).contains(&t))
+                    })
+                })
+            })
+    }
+
+    /// Initial tempo in BPM. Ultimate SoundTracker stores it in the restart
+    /// byte as a CIA timer value, all later trackers start at 125.
+    pub fn initial_tempo(&self) -> f64 {
+        if self.is_soundtracker()
+            && (1..240).contains(&self.restart_pos)
+            && self.restart_pos != 0x78
+        {
+            let tempo = (709_379.0 * 125.0 / 50.0) / ((240 - self.restart_pos) as f64 * 122.0);
+            tempo.min(255.0)
+        } else {
+            125.0
+        }
+    }
+
+    /// Sequence position to continue at after the song end
+    pub fn restart_position(&self) -> u8 {
+        if !self.is_soundtracker() && self.restart_pos < self.song_length {
+            self.restart_pos
+        } else {
+            0
+        }
+    }
+
+    pub fn serialize<W>(&self, w: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        serialize::serialize_mod(self, w)
+    }
+
+    pub fn info_str(self) -> String {
+        info::info_mod(&self)
+    }
+}
+
+/// Module parsed by `ProtrackerMod::from_bytes_borrowed`. The samples in
+/// `pt_mod` have no data, it is referenced from the input buffer instead.
+pub struct BorrowedMod<'a> {
+    pub pt_mod: ProtrackerMod,
+    sample_data: Vec<&'a [i8]>,
+}
+
+impl<'a> BorrowedMod<'a> {
+    /// Data of sample `idx` (0-based) as stored in the file. Shorter than
+    /// the sample length if the input was truncated and loaded in lenient
+    /// mode.
+    pub fn sample_data(&self, idx: usize) -> &'a [i8] {
+        self.sample_data[idx]
+    }
+
+    /// Copy the sample data into the module.
+    pub fn into_owned(self) -> ProtrackerMod {
+        let mut pt_mod = self.pt_mod;
+        for (sample, data) in pt_mod.samples.iter_mut().zip(self.sample_data) {
+            if sample.length > 0 {
+                sample.data = data.to_vec();
+                sample.data.resize(sample.length as usize, 0);
+            }
+        }
+        pt_mod
+    }
+}
+
+fn read_mod(
+    r: &mut dyn Read,
+    file_size: Option<u64>,
+    options: &LoadOptions,
+) -> Result<(ProtrackerMod, Vec<LoadWarning>), ParseError> {
+    let mut warnings = vec![];
+
+    // buffer the header to check the format tag at offset 1080
+    let mut header = vec![];
+    r.take(HEADER_SIZE).read_to_end(&mut header)?;
+    let format = FormatInfo::detect(&header)?;
+
+    let mut r = OffsetReader {
+        inner: &mut (&header[..]).chain(r),
+        offset: 0,
+    };
+    let mut pt_mod = parse_headers(&mut r, &format, file_size, options, &mut warnings)?;
+    parse_sample_data(&mut r, &mut pt_mod.samples, options, &mut warnings)?;
+    if options.lenient {
+        clamp_loops(&mut pt_mod.samples, &mut warnings);
+    }
+    Ok((pt_mod, warnings))
+}
+
+// title + 31 sample headers + pattern table + tag
+const HEADER_SIZE: u64 = 1084;
+// title + 15 sample headers + pattern table
+const ST_HEADER_SIZE: usize = 600;
+
+// parse everything up to the sample data
+fn parse_headers(
+    r: &mut OffsetReader,
+    format: &FormatInfo,
+    file_size: Option<u64>,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<ProtrackerMod, ParseError> {
+    check_limit(
+        Limit::Channels,
+        format.num_channels as u64,
+        options.limits.max_channels.map(|max| max as u64),
+    )?;
+
+    // module title
+    let title = section(parse_str(r, 20), Section::Title, r)?;
 
-        // module title
-        let title = parse_str(&mut r, 20)?;
+    // samples
+    let mut samples = vec![];
+    for _i in 0..format.num_samples {
+        samples.push(section(
+            parse_sample_param(r, format.soundtracker),
+            Section::SampleHeaders,
+            r,
+        )?);
+    }
 
-        // samples
-        let mut samples = vec![];
-        for _i in 0..num_samples {
-            samples.push(parse_sample_param(&mut r)?);
+    // parse pattern table
+    let mut table = [0; 130];
+    section(r.read_exact(&mut table), Section::PatternTable, r)?;
+    let mut song_length = table[0];
+    let restart_pos = table[1];
+    if song_length == 0 {
+        if !options.lenient {
+            return Err(ParseError::EmptyOrderList);
+        }
+        warnings.push(LoadWarning::EmptyOrderList);
+        song_length = 1;
+    } else if song_length > 128 {
+        if !options.lenient {
+            return Err(ParseError::SongLengthOutOfRange {
+                length: song_length,
+            });
         }
+        warnings.push(LoadWarning::SongLengthClamped {
+            length: song_length,
+        });
+        song_length = 128;
+    }
+    let mut pattern_table = [0; 128];
+    pattern_table.copy_from_slice(&table[2..]);
+
+    if !format.soundtracker {
+        // skip tag
+        section(r.read_exact(&mut [0; 4]), Section::Tag, r)?;
+    }
 
-        // parse pattern table
-        let song_length = r.read_u8()?;
-        r.read_u8()?; // ignore, legacy restart
-        let mut sequence = vec![];
-        for _i in 0..song_length {
-            sequence.push(r.read_u8()?);
+    // FLT8 (StarTrekker) pattern table entries refer to pairs of 4-channel patterns
+    if format.flt8 {
+        for pat in pattern_table.iter_mut() {
+            *pat /= 2;
         }
+    }
 
-        let padding = 128 - song_length as usize + 4;
-        for _i in 0..padding {
-            r.read_u8()?;
+    for (position, pattern) in pattern_table[..song_length as usize].iter_mut().enumerate() {
+        if *pattern as usize >= format.max_patterns {
+            if !options.lenient {
+                return Err(ParseError::PatternIndexOutOfRange {
+                    position: position as u8,
+                    pattern: *pattern,
+                    num_patterns: format.max_patterns,
+                });
+            }
+            warnings.push(LoadWarning::PatternIndexReplaced {
+                position: position as u8,
+                pattern: *pattern,
+            });
+            *pattern = 0;
         }
+    }
 
-        // determine number of patterns to read: max index from pattern table
-        let num_patterns = (*sequence.iter().max().unwrap() + 1).min(max_pattern);
+    let sample_bytes = samples.iter().map(|s| s.length as u64).sum();
+    check_limit(
+        Limit::SampleBytes,
+        sample_bytes,
+        options.limits.max_sample_bytes,
+    )?;
 
-        // read patterns
-        let mut patterns = vec![];
-        for _i in 0..num_patterns {
-            patterns.push(parse_pattern(&mut r, num_channels)?);
+    // determine number of patterns to read
+    let pattern_size = 64 * format.num_channels as u64 * 4;
+    let pattern_data_size =
+        file_size.map(|file_size| file_size.saturating_sub(r.offset + sample_bytes));
+    let num_patterns = num_stored_patterns(
+        &pattern_table,
+        song_length as usize,
+        format.max_patterns,
+        pattern_size,
+        pattern_data_size,
+    );
+    check_limit(
+        Limit::Patterns,
+        num_patterns as u64,
+        options.limits.max_patterns.map(|max| max as u64),
+    )?;
+
+    // streams have no known size, check the size declared by the headers
+    let length = num_patterns as u64 * pattern_size;
+    check_limit(
+        Limit::FileSize,
+        r.offset + length + sample_bytes,
+        options.limits.max_file_size,
+    )?;
+
+    // read patterns
+    let mut data = vec![];
+    r.take(length).read_to_end(&mut data)?;
+    if (data.len() as u64) < length {
+        if !options.lenient {
+            return Err(ParseError::Truncated {
+                section: Section::Patterns,
+                offset: r.offset,
+            });
+        }
+        warnings.push(LoadWarning::PatternDataTruncated {
+            length,
+            available: data.len() as u64,
+        });
+        data.resize(length as usize, 0);
+    }
+    let mut data = &data[..];
+    let mut patterns = vec![];
+    for _i in 0..num_patterns {
+        if format.flt8 {
+            patterns.push(parse_pattern_flt8(&mut data)?);
+        } else {
+            patterns.push(parse_pattern(&mut data, format.num_channels)?);
         }
+    }
+
+    Ok(ProtrackerMod {
+        title,
+        num_channels: format.num_channels,
+        samples,
+        song_length,
+        restart_pos,
+        pattern_table,
+        patterns,
+    })
+}
 
-        // read samples
-        parse_sample_data(&mut r, &mut samples)?;
+// ProTracker stores as many patterns as the highest entry in the whole
+// pattern table. Some trackers leave garbage in the entries past the song
+// end; if these disagree with the entries in the song, the size of the
+// pattern data (if known) decides.
+fn num_stored_patterns(
+    pattern_table: &[u8; 128],
+    song_length: usize,
+    max_patterns: usize,
+    pattern_size: u64,
+    pattern_data_size: Option<u64>,
+) -> usize {
+    let count = |entries: &[u8]| {
+        entries
+            .iter()
+            .map(|pat| *pat as usize)
+            .filter(|pat| *pat < max_patterns)
+            .max()
+            .map_or(0, |pat| pat + 1)
+    };
+    let num_song = count(&pattern_table[..song_length]);
+    let num_all = count(&pattern_table[..]);
+    if num_all == num_song {
+        return num_all;
+    }
 
-        Ok(ProtrackerMod {
-            title,
-            samples,
-            sequence,
-            patterns,
+    match pattern_data_size {
+        Some(size) => {
+            if size == num_all as u64 * pattern_size {
+                num_all
+            } else if size == num_song as u64 * pattern_size {
+                num_song
+            } else if size >= num_all as u64 * pattern_size {
+                num_all
+            } else {
+                num_song
+            }
+        }
+        None => num_all,
+    }
+}
+
+struct FormatInfo {
+    num_samples: u8,
+    num_channels: u8,
+    max_patterns: usize,
+    flt8: bool,
+    soundtracker: bool,
+}
+
+impl FormatInfo {
+    fn detect(header: &[u8]) -> Result<FormatInfo, ParseError> {
+        // 1080 = 20 + 31 * (22 + 2 + 1 + 1 + 2 + 2) + 1 + 1 + 128
+        if header.len() as u64 >= HEADER_SIZE {
+            let tag = parse_str(&mut &header[1080..1084], 4)?;
+            if let Some(format) = FormatInfo::from_tag(&tag) {
+                return Ok(format);
+            }
+        }
+
+        // no tag: 15-sample module, unless it does not look like one
+        if is_soundtracker_header(header) {
+            Ok(FormatInfo {
+                num_samples: 15,
+                num_channels: 4,
+                max_patterns: 64,
+                flt8: false,
+                soundtracker: true,
+            })
+        } else if (header.len() as u64) < HEADER_SIZE {
+            Err(ParseError::Truncated {
+                section: Section::Tag,
+                offset: header.len() as u64,
+            })
+        } else {
+            Err(ParseError::UnknownFormat)
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<FormatInfo> {
+        let (num_channels, max_patterns, flt8) = match tag {
+            "M.K." | "FLT4" | "4CHN" => (4, 64, false),
+            "M!K!" => (4, 256, false),
+            "FLT8" => (8, 64, true),
+            "CD61" => (6, 256, false),
+            "CD81" | "OKTA" | "OCTA" => (8, 256, false),
+            _ => (tag_channels(tag)?, 256, false),
+        };
+        Some(FormatInfo {
+            num_samples: 31,
+            num_channels,
+            max_patterns,
+            flt8,
+            soundtracker: false,
         })
     }
+}
 
-    pub fn info_str(self) -> String {
-        info::info_mod(&self)
+// Plausibility checks for tag-less files, which are otherwise
+// indistinguishable from arbitrary data.
+fn is_soundtracker_header(header: &[u8]) -> bool {
+    if header.len() < ST_HEADER_SIZE {
+        return false;
+    }
+
+    // text: printable characters or 0 padding
+    let is_text = |s: &[u8]| {
+        s.iter()
+            .all(|c| *c == 0 || (32..127).contains(c) || *c >= 160)
+    };
+    let word = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]) as u32;
+
+    if !is_text(&header[0..20]) {
+        return false;
+    }
+    for param in header[20..470].chunks(30) {
+        let length = word(&param[22..]) * 2;
+        let finetune = param[24];
+        let volume = param[25];
+        let repeat_offset = word(&param[26..]);
+        let repeat_length = word(&param[28..]) * 2;
+        if !is_text(&param[..22]) || finetune != 0 || volume > 64 {
+            return false;
+        }
+        if repeat_length > 2 && repeat_offset + repeat_length > length {
+            return false;
+        }
+    }
+
+    let song_length = header[470] as usize;
+    if song_length == 0 || song_length > 128 {
+        return false;
+    }
+    if header[472..472 + song_length].iter().any(|pat| *pat >= 64) {
+        return false;
+    }
+
+    // pattern data in the header: at most 15 samples, periods in the
+    // range of the note tables
+    header[ST_HEADER_SIZE..].chunks_exact(4).all(|cell| {
+        let period = ((cell[0] as u16 & 0x0f) << 8) | cell[1] as u16;
+        cell[0] & 0xf0 == 0 && (period == 0 || (108..=907).contains(&period))
+    })
+}
+
+// channel count from "xCHN", "xxCH" and "TDZx" tags
+fn tag_channels(tag: &str) -> Option<u8> {
+    let digits = match tag.as_bytes() {
+        [x, b'C', b'H', b'N'] => vec![*x],
+        [x, y, b'C', b'H'] => vec![*x, *y],
+        [b'T', b'D', b'Z', x] => vec![*x],
+        _ => return None,
+    };
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
     }
+    let num_channels = from_utf8(&digits).ok()?.parse::<u8>().ok()?;
+    if (1..=32).contains(&num_channels) {
+        Some(num_channels)
+    } else {
+        None
+    }
+}
+
+fn check_limit(limit: Limit, value: u64, max: Option<u64>) -> Result<(), ParseError> {
+    match max {
+        Some(max) if value > max => Err(ParseError::LimitExceeded { limit, value, max }),
+        _ => Ok(()),
+    }
+}
+
+// reader keeping track of the current file offset for error reporting
+struct OffsetReader<'a> {
+    inner: &'a mut dyn Read,
+    offset: u64,
+}
+
+impl<'a> Read for OffsetReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+// map end of file while reading a section to ParseError::Truncated
+fn section<T>(
+    result: std::io::Result<T>,
+    section: Section,
+    r: &OffsetReader,
+) -> Result<T, ParseError> {
+    result.map_err(|e| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            ParseError::Truncated {
+                section,
+                offset: r.offset,
+            }
+        } else {
+            ParseError::Io(e)
+        }
+    })
 }
 
 fn parse_str(r: &mut dyn Read, length: usize) -> std::io::Result<String> {
     // read fixed number of bytes
-    let mut buf = vec![];
-    buf.resize(length, 0);
+    let mut buf = vec![0; length];
     r.read_exact(&mut buf)?;
 
     // trim trailing 0
@@ -174,12 +726,17 @@ fn parse_str(r: &mut dyn Read, length: usize) -> std::io::Result<String> {
     }
 }
 
-fn parse_sample_param(mut r: &mut dyn Read) -> std::io::Result<Sample> {
+fn parse_sample_param(mut r: &mut dyn Read, soundtracker: bool) -> std::io::Result<Sample> {
     let name = parse_str(&mut r, 22)?;
     let length = r.read_u16::<BigEndian>()? as u32 * 2;
     let finetune = signed_nibble(r.read_u8()?);
     let volume = r.read_u8()?;
-    let repeat_offset = r.read_u16::<BigEndian>()? as u32 * 2;
+    // Ultimate SoundTracker stores the loop start in bytes, not words
+    let repeat_offset = if soundtracker {
+        r.read_u16::<BigEndian>()? as u32
+    } else {
+        r.read_u16::<BigEndian>()? as u32 * 2
+    };
     let repeat_length = r.read_u16::<BigEndian>()? as u32 * 2;
 
     Ok(Sample {
@@ -205,6 +762,22 @@ fn parse_pattern(mut r: &mut dyn Read, num_channels: u8) -> std::io::Result<Patt
     Ok(Pattern { divisions })
 }
 
+// FLT8 stores each 8-channel pattern as two consecutive 4-channel halves
+fn parse_pattern_flt8(mut r: &mut dyn Read) -> std::io::Result<Pattern> {
+    let left = parse_pattern(&mut r, 4)?;
+    let right = parse_pattern(&mut r, 4)?;
+    let divisions = left
+        .divisions
+        .into_iter()
+        .zip(right.divisions)
+        .map(|(mut l, r)| {
+            l.channel_data.extend(r.channel_data);
+            l
+        })
+        .collect();
+    Ok(Pattern { divisions })
+}
+
 fn parse_channel_data(r: &mut dyn Read) -> std::io::Result<ChannelData> {
     let mut buf = [0; 4];
     r.read_exact(&mut buf)?;
@@ -213,7 +786,7 @@ fn parse_channel_data(r: &mut dyn Read) -> std::io::Result<ChannelData> {
     let sample = (nibbles[0].0 << 4) | nibbles[2].0;
     let period =
         ((nibbles[0].1 as u16) << 8) | ((nibbles[1].0 as u16) << 4) | (nibbles[1].1 as u16);
-    let effect = parse_effect(nibbles[2].1, nibbles[3].0, nibbles[3].1)?;
+    let effect = Effect::from_nibbles(nibbles[2].1, nibbles[3].0, nibbles[3].1);
 
     Ok(ChannelData {
         sample,
@@ -222,34 +795,6 @@ fn parse_channel_data(r: &mut dyn Read) -> std::io::Result<ChannelData> {
     })
 }
 
-fn parse_effect(t: u8, x: u8, y: u8) -> std::io::Result<Effect> {
-    let effect_type = match EffectType::try_from(t) {
-        Ok(effect_type) => effect_type,
-        Err(e) => {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
-        }
-    };
-
-    if effect_type == EffectType::Extended {
-        let effect_type_extended = match EffectTypeExtended::try_from(t) {
-            Ok(effect_type_extended) => effect_type_extended,
-            Err(e) => {
-                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
-            }
-        };
-        Ok(Effect::Extended {
-            effect_type: effect_type_extended,
-            param: y,
-        })
-    } else {
-        Ok(Effect::Normal {
-            effect_type,
-            param1: x,
-            param2: y,
-        })
-    }
-}
-
 fn split_nibbles(v: &[u8]) -> Vec<(u8, u8)> {
     v.iter().map(|x| ((x & 0xf0) >> 4, x & 0x0f)).collect()
 }
@@ -258,20 +803,586 @@ fn signed_nibble(n: u8) -> i8 {
     (n & 0x7) as i8 - (n & 0x8) as i8
 }
 
-fn parse_sample_data(r: &mut dyn Read, samples: &mut Vec<Sample>) -> std::io::Result<()> {
-    for sample in samples {
+fn parse_sample_data(
+    r: &mut dyn Read,
+    samples: &mut [Sample],
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<(), ParseError> {
+    for (idx, sample) in samples.iter_mut().enumerate() {
         if sample.length > 0 {
             let mut data = vec![];
+            r.take(sample.length as u64).read_to_end(&mut data)?;
+            check_sample_length(idx, sample, data.len(), options, warnings)?;
             data.resize(sample.length as usize, 0);
-            r.read_exact(&mut data)?;
 
-            // reinterpret as i8
-            let data = data
-                .into_iter()
-                .map(|b| i8::from_be_bytes([b]))
-                .collect::<Vec<_>>();
-            sample.data = data;
+            sample.data = as_i8_slice(&data).to_vec();
         }
     }
     Ok(())
 }
+
+fn check_sample_length(
+    idx: usize,
+    sample: &Sample,
+    available: usize,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<(), ParseError> {
+    if available < sample.length as usize {
+        if !options.lenient {
+            return Err(ParseError::SampleTooLong {
+                sample: idx as u8 + 1,
+                length: sample.length,
+                available: available as u32,
+            });
+        }
+        warnings.push(LoadWarning::SampleDataTruncated {
+            sample: idx as u8 + 1,
+            length: sample.length,
+            available: available as u32,
+        });
+    }
+    Ok(())
+}
+
+// reinterpret as i8
+fn as_i8_slice(data: &[u8]) -> &[i8] {
+    // SAFETY: u8 and i8 have the same size and alignment
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const i8, data.len()) }
+}
+
+fn clamp_loops(samples: &mut [Sample], warnings: &mut Vec<LoadWarning>) {
+    for (idx, sample) in samples.iter_mut().enumerate() {
+        if sample.repeat_length > 2 && sample.repeat_offset + sample.repeat_length > sample.length {
+            warnings.push(LoadWarning::LoopClamped {
+                sample: idx as u8 + 1,
+                repeat_offset: sample.repeat_offset,
+                repeat_length: sample.repeat_length,
+            });
+            if sample.repeat_offset + 2 < sample.length {
+                sample.repeat_length = sample.length - sample.repeat_offset;
+            } else {
+                // loop starts past the end, disable
+                sample.repeat_offset = 0;
+                sample.repeat_length = 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // build a module with one 2-byte sample and `num_patterns` stored patterns
+    pub(super) fn build_mod(tag: &[u8; 4], num_channels: usize, num_patterns: u8) -> Vec<u8> {
+        let mut buf = vec![0; 20];
+        for i in 0..31 {
+            let mut header = vec![0; 30];
+            if i == 0 {
+                header[23] = 1; // length: 1 word
+                header[25] = 64; // volume
+            }
+            buf.extend(header);
+        }
+        buf.push(num_patterns);
+        buf.push(127);
+        let mut table = vec![0; 128];
+        for (pos, pat) in table.iter_mut().enumerate().take(num_patterns as usize) {
+            *pat = pos as u8;
+        }
+        buf.extend(table);
+        buf.extend(tag);
+        for pat in 0..num_patterns {
+            let mut pattern = vec![0; 64 * num_channels * 4];
+            // effect parameter of the last channel on the first division marks the pattern
+            pattern[num_channels * 4 - 1] = pat + 1;
+            buf.extend(pattern);
+        }
+        buf.extend(&[0x7f, 0x80]);
+        buf
+    }
+
+    // 15-sample module with a looped 4-byte sample, loop start in bytes
+    fn build_st_mod(num_patterns: u8) -> Vec<u8> {
+        let mut buf = b"st-test".to_vec();
+        buf.resize(20, 0);
+        for i in 0..15 {
+            let mut header = vec![0; 30];
+            if i == 0 {
+                header[..6].copy_from_slice(b"st-01:");
+                header[23] = 2; // length: 2 words
+                header[25] = 64; // volume
+                header[27] = 1; // repeat offset: 1 byte
+                header[29] = 1; // repeat length: 1 word
+            }
+            buf.extend(header);
+        }
+        buf.push(num_patterns);
+        buf.push(0x78);
+        let mut table = vec![0; 128];
+        for (pos, pat) in table.iter_mut().enumerate().take(num_patterns as usize) {
+            *pat = pos as u8;
+        }
+        buf.extend(table);
+        for _pat in 0..num_patterns {
+            let mut pattern = vec![0; 64 * 4 * 4];
+            pattern[..4].copy_from_slice(&[0x01, 0xac, 0x11, 0x37]); // C-2, 1xy arpeggio
+            buf.extend(pattern);
+        }
+        buf.extend(&[1, 2, 3, 4]);
+        buf
+    }
+
+    #[test]
+    fn test_soundtracker() {
+        let data = build_st_mod(2);
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert!(pt_mod.is_soundtracker());
+        assert!(pt_mod.has_soundtracker_effects());
+        assert_eq!("st-test", pt_mod.title);
+        assert_eq!(15, pt_mod.samples.len());
+        assert_eq!(2, pt_mod.patterns.len());
+        assert_eq!(1, pt_mod.samples[0].repeat_offset);
+        assert_eq!(2, pt_mod.samples[0].repeat_length);
+        assert_eq!(vec![1, 2, 3, 4], pt_mod.samples[0].data);
+        assert_eq!(428, pt_mod.patterns[0].divisions[0].channel_data[0].period);
+        // the info dump shows 1xy as the arpeggio it plays as
+        assert!(pt_mod.info_str().contains("01|C-2|037"));
+
+        // a sample without a note is not shown as the nearest note to period 0
+        let mut data = build_st_mod(1);
+        data[600 + 16 + 2] = 0x10;
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert!(pt_mod.info_str().contains("01|---|000"));
+
+        // streams: the buffered header extends into the pattern data
+        let pt_mod = ProtrackerMod::from_reader(&mut Cursor::new(data)).unwrap();
+        assert_eq!(vec![1, 2, 3, 4], pt_mod.samples[0].data);
+
+        // ProTracker arpeggio: later SoundTracker version
+        let mut data = build_st_mod(1);
+        data[600 + 2] = 0x10;
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert!(pt_mod.is_soundtracker());
+        assert!(!pt_mod.has_soundtracker_effects());
+
+        // sample data with negative values past the header
+        let mut data = build_st_mod(1);
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&[0x80, 0xff, 0x80, 0xff]);
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert!(pt_mod.is_soundtracker());
+        assert_eq!(vec![-128, -1, -128, -1], pt_mod.samples[0].data);
+        let streamed = ProtrackerMod::deserialize(&mut Cursor::new(data)).unwrap();
+        assert_eq!(streamed.samples[0].data, pt_mod.samples[0].data);
+    }
+
+    #[test]
+    fn test_soundtracker_garbage() {
+        let mut seed = 0x1234_5678_u32;
+        let garbage: Vec<_> = (0..4096)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        assert!(matches!(
+            ProtrackerMod::from_bytes(&garbage),
+            Err(ParseError::UnknownFormat)
+        ));
+
+        for (offset, value) in &[
+            (0, 0x07),       // control character in title
+            (20 + 24, 0x05), // finetune
+            (20 + 25, 65),   // volume
+            (470, 0),        // song length
+            (472, 64),       // pattern number
+            (600, 0x10),     // sample number 16
+            (600, 0x0f),     // period 0xfac
+        ] {
+            let mut data = build_st_mod(1);
+            data[*offset] = *value;
+            assert!(
+                ProtrackerMod::from_bytes(&data).is_err(),
+                "offset {} accepted",
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn test_tag_channels() {
+        assert_eq!(Some(6), tag_channels("6CHN"));
+        assert_eq!(Some(12), tag_channels("12CH"));
+        assert_eq!(Some(32), tag_channels("32CH"));
+        assert_eq!(Some(3), tag_channels("TDZ3"));
+        assert_eq!(None, tag_channels("33CH"));
+        assert_eq!(None, tag_channels("0CHN"));
+        assert_eq!(None, tag_channels("+1CH"));
+        assert_eq!(None, tag_channels("M.K."));
+    }
+
+    #[test]
+    fn test_effect_extended() {
+        use EffectTypeExtended::*;
+        let sub_commands = [
+            SetFilterOnOff,
+            FineslideUp,
+            FineslideDown,
+            Glissando,
+            SetVibratoWaveform,
+            SetFinetuneValue,
+            LoopPattern,
+            SetTremoloWaveform,
+            Unused,
+            RetriggerSample,
+            FineVolumeSlideUp,
+            FineVolumeSlideDown,
+            CutSample,
+            DelaySample,
+            DelayPattern,
+            InvertLoop,
+        ];
+        for xy in 0..=255u8 {
+            let (x, y) = (xy >> 4, xy & 0x0f);
+            let effect = Effect::from_nibbles(0xe, x, y);
+            match effect {
+                Effect::Extended { effect_type, param } => {
+                    assert_eq!(sub_commands[x as usize], effect_type);
+                    assert_eq!(y, param);
+                }
+                _ => panic!("E{:02x} not decoded as extended effect", xy),
+            }
+            assert_eq!((0xe, x, y), effect.to_nibbles());
+        }
+    }
+
+    #[test]
+    fn test_effect_nibbles() {
+        for txy in 0..0x1000u16 {
+            let (t, x, y) = ((txy >> 8) as u8, (txy >> 4) as u8 & 0x0f, txy as u8 & 0x0f);
+            assert_eq!((t, x, y), Effect::from_nibbles(t, x, y).to_nibbles());
+        }
+    }
+
+    #[test]
+    fn test_deserialize_multichannel() {
+        for (tag, num_channels) in &[
+            (b"M.K.", 4),
+            (b"6CHN", 6),
+            (b"10CH", 10),
+            (b"CD81", 8),
+            (b"TDZ2", 2),
+        ] {
+            let data = build_mod(tag, *num_channels, 2);
+            let pt_mod = ProtrackerMod::deserialize(&mut Cursor::new(data)).unwrap();
+            assert_eq!(*num_channels as u8, pt_mod.num_channels);
+            assert_eq!(2, pt_mod.patterns.len());
+            for pattern in pt_mod.patterns.iter() {
+                assert_eq!(*num_channels, pattern.divisions[0].channel_data.len());
+            }
+            assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |data: Vec<u8>| ProtrackerMod::deserialize(&mut Cursor::new(data));
+        let data = build_mod(b"M.K.", 4, 2);
+
+        match parse(data[..1500].to_vec()) {
+            Err(ParseError::Truncated {
+                section: Section::Patterns,
+                offset: 1500,
+            }) => {}
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+
+        match parse(data[..data.len() - 1].to_vec()) {
+            Err(ParseError::SampleTooLong {
+                sample: 1,
+                length: 2,
+                available: 1,
+            }) => {}
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+
+        let mut empty = data.clone();
+        empty[950] = 0;
+        assert!(matches!(parse(empty), Err(ParseError::EmptyOrderList)));
+
+        let mut too_long = data.clone();
+        too_long[950] = 129;
+        assert!(matches!(
+            parse(too_long),
+            Err(ParseError::SongLengthOutOfRange { length: 129 })
+        ));
+
+        let mut out_of_range = data;
+        out_of_range[953] = 64;
+        assert!(matches!(
+            parse(out_of_range),
+            Err(ParseError::PatternIndexOutOfRange {
+                position: 1,
+                pattern: 64,
+                num_patterns: 64,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_lenient() {
+        let lenient = LoadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let parse = |data: Vec<u8>| {
+            ProtrackerMod::deserialize_with_options(&mut Cursor::new(data), &lenient).unwrap()
+        };
+        let mut data = build_mod(b"M.K.", 4, 1);
+        data[950] = 0; // song length
+        data[46..48].copy_from_slice(&[0, 1]); // repeat offset: 1 word
+        data[48..50].copy_from_slice(&[0, 2]); // repeat length: 2 words
+
+        // truncated in the sample data
+        let (pt_mod, warnings) = parse(data[..data.len() - 1].to_vec());
+        assert_eq!(
+            vec![
+                LoadWarning::EmptyOrderList,
+                LoadWarning::SampleDataTruncated {
+                    sample: 1,
+                    length: 2,
+                    available: 1
+                },
+                LoadWarning::LoopClamped {
+                    sample: 1,
+                    repeat_offset: 2,
+                    repeat_length: 4
+                },
+            ],
+            warnings
+        );
+        assert_eq!(&[0], pt_mod.sequence());
+        assert_eq!(vec![127, 0], pt_mod.samples[0].data);
+        assert_eq!(0, pt_mod.samples[0].repeat_offset);
+        assert_eq!(2, pt_mod.samples[0].repeat_length);
+
+        // truncated in the pattern data
+        let mut data = build_mod(b"M.K.", 4, 2);
+        data[953] = 100;
+        let (pt_mod, warnings) = parse(data[..1500].to_vec());
+        assert_eq!(
+            vec![
+                LoadWarning::PatternIndexReplaced {
+                    position: 1,
+                    pattern: 100
+                },
+                LoadWarning::PatternDataTruncated {
+                    length: 1024,
+                    available: 416
+                },
+                LoadWarning::SampleDataTruncated {
+                    sample: 1,
+                    length: 2,
+                    available: 0
+                },
+            ],
+            warnings
+        );
+        assert_eq!(&[0, 0], pt_mod.sequence());
+        assert_eq!(1, pt_mod.patterns.len());
+        assert_eq!(vec![0, 0], pt_mod.samples[0].data);
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = build_mod(b"8CHN", 8, 3);
+        let parse = |limits: Limits| {
+            let options = LoadOptions {
+                lenient: false,
+                limits,
+            };
+            ProtrackerMod::deserialize_with_options(&mut Cursor::new(data.clone()), &options)
+        };
+
+        assert!(parse(Limits::default()).is_ok());
+        for (limits, expected) in [
+            (
+                Limits {
+                    max_file_size: Some(1000),
+                    ..Default::default()
+                },
+                (Limit::FileSize, data.len() as u64, 1000),
+            ),
+            (
+                Limits {
+                    max_channels: Some(4),
+                    ..Default::default()
+                },
+                (Limit::Channels, 8, 4),
+            ),
+            (
+                Limits {
+                    max_patterns: Some(2),
+                    ..Default::default()
+                },
+                (Limit::Patterns, 3, 2),
+            ),
+            (
+                Limits {
+                    max_sample_bytes: Some(1),
+                    ..Default::default()
+                },
+                (Limit::SampleBytes, 2, 1),
+            ),
+        ] {
+            match parse(limits) {
+                Err(ParseError::LimitExceeded { limit, value, max }) => {
+                    assert_eq!(expected, (limit, value, max))
+                }
+                r => panic!("unexpected result {:?}", r.err()),
+            }
+        }
+    }
+
+    // non-seekable reader returning at most 7 bytes per read
+    struct Stream<'a>(&'a [u8]);
+
+    impl<'a> Read for Stream<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let data = build_mod(b"6CHN", 6, 2);
+        let pt_mod = ProtrackerMod::from_reader(&mut Stream(&data)).unwrap();
+        assert_eq!(6, pt_mod.num_channels);
+        assert_eq!(2, pt_mod.patterns.len());
+        assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+
+        match ProtrackerMod::from_bytes(&data[..1000]) {
+            Err(ParseError::Truncated {
+                section: Section::Tag,
+                offset: 1000,
+            }) => {}
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_borrowed() {
+        let data = build_mod(b"M.K.", 4, 2);
+        let (borrowed, warnings) =
+            ProtrackerMod::from_bytes_borrowed(&data, &LoadOptions::default()).unwrap();
+        assert!(warnings.is_empty());
+        assert!(borrowed.pt_mod.samples[0].data.is_empty());
+        let sample_data = borrowed.sample_data(0);
+        assert_eq!(&[127, -128], sample_data);
+        assert!(sample_data[1] < 0);
+        assert_eq!(
+            data[data.len() - 2..].as_ptr() as *const i8,
+            sample_data.as_ptr()
+        );
+        assert!(borrowed.sample_data(1).is_empty());
+        assert_eq!(vec![127, -128], borrowed.into_owned().samples[0].data);
+
+        // truncated sample data
+        let truncated = &data[..data.len() - 1];
+        assert!(matches!(
+            ProtrackerMod::from_bytes_borrowed(truncated, &LoadOptions::default()),
+            Err(ParseError::SampleTooLong { .. })
+        ));
+        let lenient = LoadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let (borrowed, warnings) = ProtrackerMod::from_bytes_borrowed(truncated, &lenient).unwrap();
+        assert_eq!(1, warnings.len());
+        assert_eq!(&[127], borrowed.sample_data(0));
+        assert_eq!(vec![127, 0], borrowed.into_owned().samples[0].data);
+    }
+
+    #[test]
+    fn test_pattern_count() {
+        // pattern 2 is only referenced past the song end
+        let mut data = build_mod(b"M.K.", 4, 3);
+        data[950] = 2;
+        let pt_mod = ProtrackerMod::deserialize(&mut Cursor::new(data.clone())).unwrap();
+        assert_eq!(&[0, 1], pt_mod.sequence());
+        assert_eq!(2, pt_mod.pattern_table[2]);
+        assert_eq!(3, pt_mod.patterns.len());
+        assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+        let pt_mod = ProtrackerMod::from_reader(&mut Stream(&data)).unwrap();
+        assert_eq!(3, pt_mod.patterns.len());
+        assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+
+        // garbage past the song end, resolved by file size
+        let mut data = build_mod(b"M.K.", 4, 2);
+        data[952 + 5] = 40;
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert_eq!(2, pt_mod.patterns.len());
+        assert_eq!(40, pt_mod.pattern_table[5]);
+        assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+
+        // streams have no size, so all entries count as ProTracker does
+        assert!(matches!(
+            ProtrackerMod::from_reader(&mut Stream(&data)),
+            Err(ParseError::Truncated {
+                section: Section::Patterns,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_restart_position() {
+        let mut data = build_mod(b"M.K.", 4, 3);
+        let pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert_eq!(127, pt_mod.restart_pos);
+        assert_eq!(0, pt_mod.restart_position());
+        assert_eq!(125.0, pt_mod.initial_tempo());
+
+        data[951] = 2;
+        let mut pt_mod = ProtrackerMod::from_bytes(&data).unwrap();
+        assert_eq!(2, pt_mod.restart_position());
+
+        // Ultimate SoundTracker: initial tempo instead of restart position
+        pt_mod.samples.truncate(15);
+        assert_eq!(0, pt_mod.restart_position());
+        pt_mod.restart_pos = 0;
+        assert_eq!(125.0, pt_mod.initial_tempo());
+        pt_mod.restart_pos = 0x78;
+        assert_eq!(125.0, pt_mod.initial_tempo());
+        pt_mod.restart_pos = 100;
+        assert!((pt_mod.initial_tempo() - 103.83).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_deserialize_flt8() {
+        // two stored 4-channel patterns form one 8-channel pattern
+        let mut data = build_mod(b"FLT8", 4, 2);
+        data[952 + 1] = 0;
+        let pt_mod = ProtrackerMod::deserialize(&mut Cursor::new(data)).unwrap();
+        assert_eq!(8, pt_mod.num_channels);
+        assert_eq!(&[0, 0], pt_mod.sequence());
+        assert_eq!(1, pt_mod.patterns.len());
+        let channel_data = &pt_mod.patterns[0].divisions[0].channel_data;
+        assert_eq!(8, channel_data.len());
+        match (&channel_data[3].effect, &channel_data[7].effect) {
+            (Effect::Normal { param2: 1, .. }, Effect::Normal { param2: 2, .. }) => {}
+            _ => panic!("unexpected FLT8 channel layout"),
+        }
+        assert_eq!(vec![127, -128], pt_mod.samples[0].data);
+    }
+}
@@ -3,7 +3,6 @@ use std::fmt;
 pub struct Note {
     octave: u8, // 0..2
     tone: u8,   // 0..11
-    exact: bool,
 }
 
 impl fmt::Display for Note {
@@ -12,31 +11,91 @@ impl fmt::Display for Note {
     }
 }
 
-pub fn get_note(finetune: i8, period: u16) -> Note {
+/// Note nearest to `period`, or `None` for period 0 (no note).
+pub fn get_note(finetune: i8, period: u16) -> Option<Note> {
+    if period == 0 {
+        return None;
+    }
     let ft_idx = (8 + finetune) as usize;
-    match NOTES[ft_idx].binary_search_by(|probe| probe.cmp(&period).reverse()) {
+    let note = match NOTES[ft_idx].binary_search_by(|probe| probe.cmp(&period).reverse()) {
         Ok(idx) => {
             let octave = idx as u8 / 12;
             let tone = idx as u8 % 12;
-            Note {
-                octave,
-                tone,
-                exact: true,
-            }
+            Note { octave, tone }
         }
         Err(idx) => {
             let idx = if idx > 0 { idx - 1 } else { 0 };
             let octave = idx as u8 / 12;
             let tone = idx as u8 % 12;
-            Note {
-                octave,
-                tone,
-                exact: false,
-            }
+            Note { octave, tone }
         }
+    };
+    Some(note)
+}
+
+/// Period `semitones` above `period`, looked up the way ProTracker's arpeggio
+/// does it.
+///
+/// PT stores the tables ordered by finetune 0..7, -8..-1, each row followed by
+/// a zero, and scans 37 entries for the first period not above the current
+/// one. The offset is then added without bounds checks, so notes past B-3
+/// read the terminating zero (muting the channel for that tick) or continue
+/// into the next finetune's row. Past the last row PT reads unrelated memory;
+/// this is treated as period 0 as well.
+pub(crate) fn arpeggio_period(finetune: i8, period: u16, semitones: u8) -> u16 {
+    let row = (finetune & 0x0f) as usize;
+    let base = pt_row(row)
+        .iter()
+        .position(|p| period >= *p)
+        .unwrap_or(PT_ROW_LEN - 1);
+    let idx = base + semitones as usize;
+    let (row, idx) = (row + idx / PT_ROW_LEN, idx % PT_ROW_LEN);
+    if row < 16 {
+        pt_row(row).get(idx).copied().unwrap_or(0)
+    } else {
+        0
+    }
+}
+
+/// Period played for a note entered as `period`.
+///
+/// As in PT, a note of the finetune 0 row is replaced by the same note of the
+/// `finetune` row. Periods which are not a note are played as they are, since
+/// the player has always played the raw period of unfinetuned samples.
+pub(crate) fn note_period(finetune: i8, period: u16) -> u16 {
+    match pt_row(0)[..PT_ROW_LEN - 1]
+        .iter()
+        .position(|p| period == *p)
+    {
+        Some(idx) if finetune != 0 => pt_row((finetune & 0x0f) as usize)[idx],
+        _ => period,
     }
 }
 
+/// Tone portamento target for a note entered as `period`.
+///
+/// PT looks the note up in the `finetune` row directly, then compensates for
+/// negative finetunes (whose periods are higher) by going up one note.
+pub(crate) fn tone_porta_period(finetune: i8, period: u16) -> u16 {
+    let row = pt_row((finetune & 0x0f) as usize);
+    let mut idx = row
+        .iter()
+        .position(|p| period >= *p)
+        .unwrap_or(PT_ROW_LEN - 1);
+    if finetune < 0 && idx > 0 {
+        idx -= 1;
+    }
+    row.get(idx).copied().unwrap_or(0)
+}
+
+// number of entries per finetune row in PT's table, including the zero
+const PT_ROW_LEN: usize = 37;
+
+// row of PT's period table (finetune 0..7, -8..-1) without the terminating zero
+fn pt_row(row: usize) -> &'static [u16; 36] {
+    &NOTES[(row + 8) % 16]
+}
+
 static TONE_NAMES: [&str; 12] = [
     "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
 ];
@@ -150,4 +209,40 @@ mod tests {
         assert_eq!("G-2".to_owned(), format!("{}", get_note(3, 280).unwrap()));
         assert_eq!("D#3".to_owned(), format!("{}", get_note(-7, 189).unwrap()));
     }
+
+    #[test]
+    fn test_no_note() {
+        assert!(get_note(0, 0).is_none());
+        assert!(get_note(-8, 0).is_none());
+    }
+
+    #[test]
+    fn test_arpeggio_period() {
+        assert_eq!(214, arpeggio_period(0, 214, 0));
+        assert_eq!(170, arpeggio_period(0, 214, 4));
+        assert_eq!(143, arpeggio_period(0, 214, 7));
+        assert_eq!(202, arpeggio_period(-8, 214, 1));
+        // inexact periods snap to the next lower note of the row
+        assert_eq!(202, arpeggio_period(0, 210, 0));
+        // past B-3: terminating zero, then the next finetune's row
+        assert_eq!(0, arpeggio_period(0, 120, 2));
+        assert_eq!(850, arpeggio_period(0, 120, 3));
+        assert_eq!(907, arpeggio_period(7, 113, 2));
+        assert_eq!(0, arpeggio_period(-1, 113, 15));
+    }
+
+    #[test]
+    fn test_finetune_periods() {
+        assert_eq!(428, note_period(0, 428));
+        assert_eq!(431, note_period(-1, 428));
+        assert_eq!(226, note_period(-8, 214));
+        // periods which are not notes are left alone
+        assert_eq!(100, note_period(3, 100));
+        assert_eq!(216, note_period(-8, 216));
+        assert_eq!(420, note_period(0, 420));
+
+        assert_eq!(428, tone_porta_period(0, 428));
+        assert_eq!(431, tone_porta_period(-1, 428));
+        assert_eq!(425, tone_porta_period(1, 428));
+    }
 }
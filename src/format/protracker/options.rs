@@ -0,0 +1,19 @@
+/// Options for `ProtrackerMod::deserialize_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    /// Repair damaged modules instead of failing, reporting each repair
+    /// as a `LoadWarning`.
+    pub lenient: bool,
+    /// Resource limits, checked before any allocation sized from the file.
+    pub limits: Limits,
+}
+
+/// Resource limits for loading untrusted modules. `None` means unlimited.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub max_file_size: Option<u64>,
+    /// Total sample data of all samples, in bytes
+    pub max_sample_bytes: Option<u64>,
+    pub max_patterns: Option<usize>,
+    pub max_channels: Option<u8>,
+}
@@ -0,0 +1,204 @@
+use super::*;
+use byteorder::WriteBytesExt;
+use std::io::{Error, ErrorKind, Write};
+
+pub fn serialize_mod(pt_mod: &ProtrackerMod, w: &mut dyn Write) -> std::io::Result<()> {
+    let tag = format_tag(pt_mod)?;
+
+    // module title
+    write_str(w, &pt_mod.title, 20)?;
+
+    // samples, always 31 (empty headers for missing samples)
+    if pt_mod.samples.len() > 31 {
+        return Err(invalid_input("more than 31 samples"));
+    }
+    for sample in pt_mod.samples.iter() {
+        write_sample_param(w, sample)?;
+    }
+    for _i in pt_mod.samples.len()..31 {
+        w.write_all(&[0; 30])?;
+    }
+
+    // pattern table
+    if pt_mod.song_length == 0 || pt_mod.song_length > 128 {
+        return Err(invalid_input("song length must be 1..128"));
+    }
+    w.write_u8(pt_mod.song_length)?;
+    w.write_u8(pt_mod.restart_pos)?;
+    w.write_all(&pt_mod.pattern_table)?;
+
+    w.write_all(tag.as_bytes())?;
+
+    // patterns
+    for pattern in pt_mod.patterns.iter() {
+        write_pattern(w, pattern, pt_mod.num_channels)?;
+    }
+
+    // sample data
+    for sample in pt_mod.samples.iter() {
+        write_sample_data(w, sample)?;
+    }
+
+    Ok(())
+}
+
+fn format_tag(pt_mod: &ProtrackerMod) -> std::io::Result<String> {
+    match pt_mod.num_channels {
+        4 => {
+            if pt_mod.patterns.len() > 64 {
+                Ok(String::from("M!K!"))
+            } else {
+                Ok(String::from("M.K."))
+            }
+        }
+        1..=9 => Ok(format!("{}CHN", pt_mod.num_channels)),
+        10..=32 => Ok(format!("{}CH", pt_mod.num_channels)),
+        _ => Err(invalid_input("channel count must be 1..32")),
+    }
+}
+
+fn write_str(w: &mut dyn Write, s: &str, length: usize) -> std::io::Result<()> {
+    // truncate or pad with trailing 0
+    let mut buf = vec![0; length];
+    let bytes = s.as_bytes();
+    let n = bytes.len().min(length);
+    buf[..n].copy_from_slice(&bytes[..n]);
+    w.write_all(&buf)
+}
+
+fn write_sample_param(w: &mut dyn Write, sample: &Sample) -> std::io::Result<()> {
+    write_str(w, &sample.name, 22)?;
+    w.write_u16::<BigEndian>(word_count(sample.length)?)?;
+    w.write_u8(sample.finetune as u8 & 0x0f)?;
+    w.write_u8(sample.volume)?;
+    w.write_u16::<BigEndian>(word_count(sample.repeat_offset)?)?;
+    w.write_u16::<BigEndian>(word_count(sample.repeat_length)?)?;
+    Ok(())
+}
+
+fn word_count(length: u32) -> std::io::Result<u16> {
+    if length / 2 > u16::MAX as u32 {
+        Err(invalid_input("sample length or loop exceeds 128 KiB"))
+    } else {
+        Ok((length / 2) as u16)
+    }
+}
+
+fn write_pattern(w: &mut dyn Write, pattern: &Pattern, num_channels: u8) -> std::io::Result<()> {
+    if pattern.divisions.len() != 64 {
+        return Err(invalid_input("pattern must have 64 divisions"));
+    }
+    for division in pattern.divisions.iter() {
+        if division.channel_data.len() != num_channels as usize {
+            return Err(invalid_input("division channel count mismatch"));
+        }
+        for channel in division.channel_data.iter() {
+            write_channel_data(w, channel)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_channel_data(w: &mut dyn Write, channel: &ChannelData) -> std::io::Result<()> {
+    if channel.period > 0x0fff {
+        return Err(invalid_input("period exceeds 12 bits"));
+    }
+    let (t, x, y) = channel.effect.to_nibbles();
+    let buf = [
+        (channel.sample & 0xf0) | (channel.period >> 8) as u8,
+        (channel.period & 0xff) as u8,
+        ((channel.sample & 0x0f) << 4) | t,
+        (x << 4) | y,
+    ];
+    w.write_all(&buf)
+}
+
+fn write_sample_data(w: &mut dyn Write, sample: &Sample) -> std::io::Result<()> {
+    // write exactly `length` bytes, padding missing data with silence
+    let length = sample.length as usize;
+    let mut data: Vec<_> = sample
+        .data
+        .iter()
+        .take(length)
+        .map(|v| v.to_be_bytes()[0])
+        .collect();
+    data.resize(length, 0);
+    w.write_all(&data)
+}
+
+fn invalid_input(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::build_mod;
+    use super::*;
+    use std::io::Cursor;
+
+    // fill pattern data with pseudo-random notes and effects
+    fn scramble_patterns(data: &mut [u8], num_channels: usize, num_patterns: usize) {
+        let mut seed = 0x1234_5678_u32;
+        for cell in data[1084..1084 + num_patterns * 64 * num_channels * 4].chunks_mut(4) {
+            for b in cell.iter_mut() {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                *b = (seed >> 16) as u8;
+            }
+            cell[0] &= 0x1f; // sample number <= 31
+        }
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let pt_mod = ProtrackerMod::deserialize(&mut Cursor::new(data.to_vec())).unwrap();
+        let mut out = vec![];
+        pt_mod.serialize(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (tag, num_channels) in &[(b"M.K.", 4), (b"6CHN", 6), (b"16CH", 16)] {
+            let mut data = build_mod(tag, *num_channels, 3);
+            data[0..4].copy_from_slice(b"test");
+            data[20..26].copy_from_slice(b"sample");
+            data[44] = 0x0d; // finetune -3
+            data[950] = 2; // pattern 2 only past the song end
+            scramble_patterns(&mut data, *num_channels, 3);
+            assert_eq!(data, round_trip(&data));
+        }
+    }
+
+    #[test]
+    fn test_format_tag() {
+        let data = build_mod(b"M.K.", 4, 1);
+        let mut pt_mod = ProtrackerMod::deserialize(&mut Cursor::new(data)).unwrap();
+        assert_eq!("M.K.", format_tag(&pt_mod).unwrap());
+
+        for _i in 0..64 {
+            pt_mod.patterns.push(Pattern { divisions: vec![] });
+        }
+        assert_eq!("M!K!", format_tag(&pt_mod).unwrap());
+
+        pt_mod.num_channels = 8;
+        assert_eq!("8CHN", format_tag(&pt_mod).unwrap());
+        pt_mod.num_channels = 24;
+        assert_eq!("24CH", format_tag(&pt_mod).unwrap());
+        pt_mod.num_channels = 33;
+        assert!(format_tag(&pt_mod).is_err());
+    }
+
+    #[test]
+    fn test_flt8_as_8chn() {
+        let data = build_mod(b"FLT8", 4, 2);
+        let pt_mod = ProtrackerMod::deserialize(&mut Cursor::new(data)).unwrap();
+        let mut out = vec![];
+        pt_mod.serialize(&mut out).unwrap();
+        assert_eq!(b"8CHN", &out[1080..1084]);
+
+        let reloaded = ProtrackerMod::deserialize(&mut Cursor::new(out)).unwrap();
+        assert_eq!(8, reloaded.num_channels);
+        assert_eq!(pt_mod.sequence(), reloaded.sequence());
+        assert_eq!(pt_mod.patterns.len(), reloaded.patterns.len());
+        assert_eq!(pt_mod.samples[0].data, reloaded.samples[0].data);
+    }
+}
@@ -1,5 +1,7 @@
 pub mod protracker;
 
+use std::fmt;
+
 pub struct OutputFormat {
     pub sample_rate: u32,
     pub sample_format: SampleFormat,
@@ -26,10 +28,62 @@ pub enum SampleBuffer {
 
 #[derive(Debug)]
 pub enum InitError {
+    /// Output channel count other than 1 (mono) or 2 (stereo)
     ChannelCountError,
+    /// Output sample rate of 0
+    SampleRateError,
+    /// Module has no channels
+    NoChannels,
+    /// Module has an empty sequence
+    EmptySequence,
 }
 
 #[derive(Debug)]
 pub enum PlayError {
-    Other,
+    /// Sequence position refers to a pattern which does not exist
+    PatternIndexOutOfRange { position: usize, pattern: usize },
+    /// Pattern data refers to a sample which does not exist
+    SampleIndexOutOfRange {
+        pattern: usize,
+        division: usize,
+        channel: usize,
+        sample: u8,
+    },
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::ChannelCountError => write!(f, "output must have 1 or 2 channels"),
+            InitError::SampleRateError => write!(f, "output sample rate must not be 0"),
+            InitError::NoChannels => write!(f, "module has no channels"),
+            InitError::EmptySequence => write!(f, "module sequence is empty"),
+        }
+    }
 }
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayError::PatternIndexOutOfRange { position, pattern } => write!(
+                f,
+                "pattern {:02x} at position {:02x} does not exist",
+                pattern, position
+            ),
+            PlayError::SampleIndexOutOfRange {
+                pattern,
+                division,
+                channel,
+                sample,
+            } => write!(
+                f,
+                "sample {:02x} in pattern {:02x}, division {:02x}, channel {} does not exist",
+                sample, pattern, division, channel
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
+impl std::error::Error for PlayError {}
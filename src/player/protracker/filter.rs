@@ -0,0 +1,160 @@
+// analog output stage of the Amiga: fixed RC filters and the LED filter
+
+use std::f64::consts::PI;
+
+/// Amiga model whose output filters are emulated.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AmigaFilter {
+    /// No filtering, the plain mix
+    #[default]
+    Off,
+    /// A500: 1-pole low pass at ~4.4 kHz, always active
+    A500,
+    /// A1200: fixed low pass at ~34 kHz, which leaves the audible range
+    /// almost untouched
+    A1200,
+}
+
+// RC low pass: A500 360 ohm / 0.1 uF, A1200 680 ohm / 6.8 nF
+const A500_LOW_PASS: f64 = 4420.97;
+const A1200_LOW_PASS: f64 = 34419.32;
+// DC blocking RC high pass of both models, 1390 ohm / 22 uF
+const HIGH_PASS: f64 = 5.2;
+// 2-pole Butterworth, switched by E0x
+const LED_CUTOFF: f64 = 3300.0;
+
+// 1-pole RC filter
+struct OnePole {
+    coeff: f64,
+    state: f64,
+}
+
+impl OnePole {
+    fn new(cutoff: f64, sample_rate: f64) -> OnePole {
+        OnePole {
+            coeff: 1.0 - (-2.0 * PI * cutoff / sample_rate).exp(),
+            state: 0.0,
+        }
+    }
+
+    fn low_pass(&mut self, input: f64) -> f64 {
+        self.state += self.coeff * (input - self.state);
+        self.state
+    }
+
+    fn high_pass(&mut self, input: f64) -> f64 {
+        input - self.low_pass(input)
+    }
+}
+
+// 2-pole Butterworth low pass, bilinear transform in direct form I
+struct Butterworth {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Butterworth {
+    fn new(cutoff: f64, sample_rate: f64) -> Butterworth {
+        // the bilinear transform needs the cutoff below the Nyquist frequency
+        let k = (PI * cutoff.min(0.45 * sample_rate) / sample_rate).tan();
+        let q = std::f64::consts::FRAC_1_SQRT_2;
+        let norm = 1.0 / (1.0 + k / q + k * k);
+        let b0 = k * k * norm;
+        Butterworth {
+            b: [b0, 2.0 * b0, b0],
+            a: [2.0 * (k * k - 1.0) * norm, (1.0 - k / q + k * k) * norm],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// Filter chain for one output channel.
+pub struct OutputFilter {
+    low_pass: OnePole,
+    led: Butterworth,
+    high_pass: OnePole,
+}
+
+impl OutputFilter {
+    pub fn new(model: AmigaFilter, sample_rate: u32) -> Option<OutputFilter> {
+        let sample_rate = sample_rate as f64;
+        let low_pass = match model {
+            AmigaFilter::Off => return None,
+            AmigaFilter::A500 => A500_LOW_PASS,
+            AmigaFilter::A1200 => A1200_LOW_PASS,
+        };
+        Some(OutputFilter {
+            low_pass: OnePole::new(low_pass, sample_rate),
+            led: Butterworth::new(LED_CUTOFF, sample_rate),
+            high_pass: OnePole::new(HIGH_PASS, sample_rate),
+        })
+    }
+
+    pub fn process(&mut self, input: f64, led: bool) -> f64 {
+        let mut val = self.low_pass.low_pass(input);
+        if led {
+            val = self.led.process(val);
+        } else {
+            // keep the LED filter settled for when it is switched on
+            self.led.process(val);
+        }
+        self.high_pass.high_pass(val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // gain for a sine at `freq` Hz, after the filter has settled
+    fn gain(model: AmigaFilter, led: bool, freq: f64) -> f64 {
+        let sample_rate = 44100;
+        let mut filter = OutputFilter::new(model, sample_rate).unwrap();
+        let mut peak: f64 = 0.0;
+        for n in 0..sample_rate {
+            let input = (2.0 * PI * freq * n as f64 / sample_rate as f64).sin();
+            let output = filter.process(input, led);
+            if n > sample_rate / 2 {
+                peak = peak.max(output.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn test_response() {
+        assert!(OutputFilter::new(AmigaFilter::Off, 44100).is_none());
+
+        // pass band
+        for model in [AmigaFilter::A500, AmigaFilter::A1200].iter() {
+            assert!((gain(*model, false, 500.0) - 1.0).abs() < 0.02);
+            assert!((gain(*model, true, 500.0) - 1.0).abs() < 0.02);
+        }
+
+        // -3 dB at the cutoff frequencies
+        assert!((gain(AmigaFilter::A500, false, A500_LOW_PASS) - 0.707).abs() < 0.02);
+        assert!((gain(AmigaFilter::A1200, true, LED_CUTOFF) - 0.707).abs() < 0.02);
+
+        // one pole falls off by 6 dB, two more poles by 18 dB per octave
+        assert!(gain(AmigaFilter::A1200, false, 10000.0) > 0.95);
+        let a500 = gain(AmigaFilter::A500, false, 10000.0);
+        assert!(a500 > 0.35 && a500 < 0.45);
+        let a500_led = gain(AmigaFilter::A500, true, 10000.0);
+        assert!(a500_led < a500 * 0.12);
+
+        // DC is removed
+        assert!(gain(AmigaFilter::A500, false, 1.0) < 0.25);
+    }
+}
@@ -1,12 +1,84 @@
 use super::{InitError, OutputFormat, PlayError, SampleBuffer, SampleFormat, SampleOutput};
-use crate::format::protracker::{Effect, EffectType, EffectTypeExtended, ProtrackerMod};
+use crate::format::protracker::{
+    arpeggio_period, note_period, tone_porta_period, Command, ProtrackerMod, Sample,
+};
+
+mod filter;
+mod interpolation;
+mod oscillator;
+mod output;
+mod paula;
+
+pub use filter::AmigaFilter;
+use filter::OutputFilter;
+pub use interpolation::Interpolation;
+use interpolation::SampleView;
+use oscillator::Oscillator;
+pub use output::Clipping;
+use output::Limiter;
+pub use paula::Mixer;
+use paula::Paula;
 
 pub struct ProtrackerPlayer {
     pt_mod: ProtrackerMod,
     clock_freq: ClockFreq,
     output_format: OutputFormat,
+    options: PlayerOptions,
+    soundtracker_effects: bool,
     state: PlayerState,
     buffer: SampleBuffer,
+    // copies of the samples changed by EFx, the module itself is left alone
+    funk_data: Vec<Option<Vec<i8>>>,
+    // one per output channel, if enabled
+    filters: Vec<OutputFilter>,
+    paula: Option<Paula>,
+    limiter: Option<Limiter>,
+    // the output still delayed by the mixer was returned after the song end
+    tail_done: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerOptions {
+    /// Continue at the module's restart position after the song end
+    /// instead of stopping. The song then never ends.
+    pub loop_song: bool,
+    /// Stereo separation in percent, from 0 (mono) to 100 (hard left/right
+    /// panning as on the Amiga)
+    pub stereo_separation: u8,
+    /// Apply 8xx (00 left .. ff right) and E8x panning. ProTracker ignores
+    /// these, but multichannel modules from PC trackers use them.
+    pub pan_effects: bool,
+    /// Resampling of the sample data to the output rate
+    pub interpolation: Interpolation,
+    /// Emulate the output filters of an Amiga model, with the LED filter
+    /// switched by E0x
+    pub filter: AmigaFilter,
+    /// Resampling mixer or Paula emulation; the interpolation is only used
+    /// by the resampler
+    pub mixer: Mixer,
+    /// Time ticks with the CIA timer's integer divisor as PT does, instead
+    /// of the exact BPM. Both are close to 50 Hz at 125 BPM.
+    pub cia_timing: bool,
+    /// Gain applied to the mix, 1.0 keeps it within full scale
+    pub master_gain: f64,
+    /// Treatment of a mix above full scale
+    pub clipping: Clipping,
+}
+
+impl Default for PlayerOptions {
+    fn default() -> PlayerOptions {
+        PlayerOptions {
+            loop_song: false,
+            stereo_separation: 100,
+            pan_effects: false,
+            interpolation: Interpolation::default(),
+            filter: AmigaFilter::default(),
+            mixer: Mixer::default(),
+            cia_timing: false,
+            master_gain: 1.0,
+            clipping: Clipping::default(),
+        }
+    }
 }
 
 pub enum ClockFreq {
@@ -17,6 +89,21 @@ pub enum ClockFreq {
 static CLOCK_FREQ_PAL: f64 = 7_093_789.2;
 static CLOCK_FREQ_NTSC: f64 = 7_159_090.5;
 
+// PT's CIA timer value is this divided by the BPM, counted down at a tenth
+// of the CPU clock
+static CIA_TEMPO_PAL: u64 = 1_773_447;
+static CIA_TEMPO_NTSC: u64 = 1_789_773;
+
+// a full scale sample (-128) at volume 64
+const CHANNEL_FULL_SCALE: f64 = 128.0 * 64.0;
+
+// period range reachable by slides (B-3 .. C-1 at finetune 0)
+const PERIOD_MIN: i32 = 113;
+const PERIOD_MAX: i32 = 856;
+
+// EFx speeds, added to the funk counter every tick until it reaches 128
+static FUNK_TABLE: [u8; 16] = [0, 5, 6, 7, 8, 10, 11, 13, 16, 19, 22, 26, 32, 43, 64, 128];
+
 struct PlayerState {
     pub sequence_pos: usize,
     pub cur_pattern: usize,
@@ -24,17 +111,72 @@ struct PlayerState {
     pub cur_tick: u8,
     pub ticks_per_min: u16,
     pub ticks_per_div: u8,
+    // output samples owed from previous ticks, 0..1
+    pub sample_frac: f64,
     pub channels: Vec<ChannelState>,
+    // state of the random vibrato / tremolo waveform
+    pub rng: u32,
+    // division to continue at after a jump (PT's PBreakPos)
+    pub break_row: u8,
+    // pattern loop jump within the pattern (PBreakFlag)
+    pub break_flag: bool,
+    // Bxx or Dxx on this division (PosJumpAssert)
+    pub position_jump: bool,
+    pub jump_target: Option<u8>,
+    // EEx: set on the division (PattDelTime), then counted down (PattDelTime2)
+    pub pattern_delay: u8,
+    pub delay_repeat: u8,
+    // E0x: LED filter on
+    pub led_filter: bool,
+    // one bit per division for each sequence position
+    pub visited: Vec<u64>,
 }
 
 struct ChannelState {
+    // sample playing and sample selected for the next note
     pub sample_no: Option<u8>,
+    pub instrument: Option<u8>,
     pub volume: u16,
     pub volume_diff: i16,
     pub offset: f64,
+    // PT's sample start and length in bytes, moved by 9xx
+    pub sample_start: u32,
+    pub sample_length: u32,
+    pub offset_memory: u8,
+    // end of the part played before entering the loop
+    pub sample_end: u32,
     pub period: u16,
     pub period_diff: i16,
     pub period_target: Option<u16>,
+    pub tone_porta_active: bool,
+    // last nonzero 1xx, 2xx and 3xx parameters
+    pub porta_up_speed: u8,
+    pub porta_down_speed: u8,
+    pub tone_porta_speed: u8,
+    // E3x: round tone portamento to semitones
+    pub glissando: bool,
+    pub finetune: i8,
+    pub arpeggio: Option<(u8, u8)>,
+    pub vibrato: Oscillator,
+    pub vibrato_active: bool,
+    pub tremolo: Oscillator,
+    pub tremolo_active: bool,
+    // ECx, EDx (with the held sample and period) and E9x on this division
+    pub note_cut: Option<u8>,
+    pub note_delay: Option<(u8, u8, u16)>,
+    pub retrigger: u8,
+    // period and volume seen by the mixer for the current tick
+    pub output_period: u16,
+    pub output_volume: u16,
+    // E6x loop start and remaining repetitions
+    pub loop_row: u8,
+    pub loop_count: u8,
+    // 0 (left) .. 255 (right)
+    pub pan: u8,
+    // EFx speed and counter, and the last inverted position in the loop
+    pub funk_speed: u8,
+    pub funk_offset: u8,
+    pub funk_pos: u32,
     pub in_loop: bool,
 }
 
@@ -43,278 +185,619 @@ impl ProtrackerPlayer {
         pt_mod: ProtrackerMod,
         clock_freq: ClockFreq,
         output_format: OutputFormat,
+    ) -> Result<ProtrackerPlayer, InitError> {
+        ProtrackerPlayer::with_options(pt_mod, clock_freq, output_format, PlayerOptions::default())
+    }
+
+    pub fn with_options(
+        pt_mod: ProtrackerMod,
+        clock_freq: ClockFreq,
+        output_format: OutputFormat,
+        options: PlayerOptions,
     ) -> Result<ProtrackerPlayer, InitError> {
         if output_format.channel_count < 1 || output_format.channel_count > 2 {
             Err(InitError::ChannelCountError)
+        } else if output_format.sample_rate == 0 {
+            Err(InitError::SampleRateError)
+        } else if pt_mod.num_channels == 0 {
+            Err(InitError::NoChannels)
+        } else if pt_mod.sequence().is_empty() {
+            Err(InitError::EmptySequence)
         } else {
             let buffer = match output_format.sample_format {
                 SampleFormat::I16 => SampleBuffer::I16(vec![]),
                 SampleFormat::U16 => SampleBuffer::U16(vec![]),
                 SampleFormat::F32 => SampleBuffer::F32(vec![]),
             };
-            let num_channels = pt_mod.patterns[0].divisions[0].channel_data.len();
-            let start_pattern = pt_mod.sequence[0];
+            let num_channels = pt_mod.num_channels as usize;
+            let start_pattern = pt_mod.sequence()[0];
+            let ticks_per_min = (4.0 * 6.0 * pt_mod.initial_tempo()).round() as u16;
+            let soundtracker_effects = pt_mod.has_soundtracker_effects();
+            let funk_data = pt_mod.samples.iter().map(|_| None).collect();
+            let filters = (0..output_format.channel_count)
+                .filter_map(|_| OutputFilter::new(options.filter, output_format.sample_rate))
+                .collect();
+            let paula = match options.mixer {
+                Mixer::Resampler => None,
+                Mixer::Paula { .. } => {
+                    let cf = match clock_freq {
+                        ClockFreq::Pal => CLOCK_FREQ_PAL,
+                        ClockFreq::Ntsc => CLOCK_FREQ_NTSC,
+                    };
+                    Some(Paula::new(
+                        num_channels,
+                        cf,
+                        output_format.sample_rate,
+                        options.mixer,
+                    ))
+                }
+            };
+            let limiter = match options.clipping {
+                Clipping::Limiter => Some(Limiter::new(output_format.sample_rate)),
+                _ => None,
+            };
             let mut player = ProtrackerPlayer {
                 pt_mod,
                 clock_freq,
                 output_format,
+                options,
+                soundtracker_effects,
                 state: PlayerState::default(num_channels),
                 buffer,
+                funk_data,
+                filters,
+                paula,
+                limiter,
+                tail_done: false,
             };
             player.state.cur_pattern = start_pattern as usize;
+            player.state.ticks_per_min = ticks_per_min;
             Ok(player)
         }
     }
 
-    pub fn get_samples(&mut self) -> Result<SampleOutput, PlayError> {
-        if self.state.sequence_pos >= self.pt_mod.sequence.len() {
-            // song finished, return empty slice
-            match &self.buffer {
-                SampleBuffer::I16(_) => Ok(SampleOutput::I16(&[])),
-                SampleBuffer::U16(_) => Ok(SampleOutput::U16(&[])),
-                SampleBuffer::F32(_) => Ok(SampleOutput::F32(&[])),
-            }
-        } else {
-            self.next_tick();
+    /// Give back the module, unchanged by playback.
+    pub fn into_mod(self) -> ProtrackerMod {
+        self.pt_mod
+    }
 
-            match &self.buffer {
-                SampleBuffer::I16(buf) => Ok(SampleOutput::I16(&buf)),
-                SampleBuffer::U16(buf) => Ok(SampleOutput::U16(&buf)),
-                SampleBuffer::F32(buf) => Ok(SampleOutput::F32(&buf)),
+    pub fn get_samples(&mut self) -> Result<SampleOutput<'_>, PlayError> {
+        if self.state.sequence_pos < self.pt_mod.sequence().len() {
+            self.next_tick()?;
+        } else {
+            // song finished: return the output still delayed by the mixer
+            // once, with the channels muted, then empty slices
+            let tail_len = if self.tail_done {
+                0
+            } else {
+                self.paula.as_ref().map_or(0, Paula::tail_len)
+                    + self.limiter.as_ref().map_or(0, Limiter::lookahead)
+            };
+            self.tail_done = true;
+            for channel in self.state.channels.iter_mut() {
+                channel.sample_no = None;
             }
+            self.resize_buffer(tail_len);
+            self.calc_output_samples(tail_len);
+        }
+
+        match &self.buffer {
+            SampleBuffer::I16(buf) => Ok(SampleOutput::I16(buf)),
+            SampleBuffer::U16(buf) => Ok(SampleOutput::U16(buf)),
+            SampleBuffer::F32(buf) => Ok(SampleOutput::F32(buf)),
         }
     }
 
-    fn next_tick(&mut self) {
+    fn next_tick(&mut self) -> Result<(), PlayError> {
         if self.state.cur_tick == 0 {
             // new division
 
-            // parse notes & effects and set parameters
-            self.update_division();
-
-            let samples_per_tick = (self.output_format.sample_rate as f32 * 60.0
-                / self.state.ticks_per_min as f32)
-                .floor() as usize;
-            match &mut self.buffer {
-                SampleBuffer::I16(buf) => {
-                    buf.resize(
-                        samples_per_tick * self.output_format.channel_count as usize,
-                        0,
-                    );
-                }
-                SampleBuffer::U16(buf) => {
-                    buf.resize(
-                        samples_per_tick * self.output_format.channel_count as usize,
-                        0,
-                    );
-                }
-                SampleBuffer::F32(buf) => {
-                    buf.resize(
-                        samples_per_tick * self.output_format.channel_count as usize,
-                        0.0,
-                    );
-                }
-            };
+            // parse notes & effects and set parameters, unless the division
+            // is repeated by a pattern delay
+            if self.state.delay_repeat == 0 {
+                self.update_division()?;
+            }
         }
+
+        let samples_per_tick = self.samples_per_tick();
+        self.resize_buffer(samples_per_tick);
         self.update_tick();
 
-        self.calc_output_samples();
+        self.calc_output_samples(samples_per_tick);
 
         self.state.cur_tick += 1;
-        self.state.cur_tick %= self.state.ticks_per_div;
+        if self.state.cur_tick >= self.state.ticks_per_div {
+            self.state.cur_tick = 0;
+            self.next_division();
+        }
+        Ok(())
+    }
 
-        if self.state.cur_tick == 0 {
-            // advance division
-            self.state.cur_division += 1;
-            self.state.cur_division %= 64;
-            if self.state.cur_division == 0 {
-                // advance sequence position
-                self.state.sequence_pos += 1;
-                if self.state.sequence_pos < self.pt_mod.sequence.len() {
-                    self.state.cur_pattern = self.pt_mod.sequence[self.state.sequence_pos] as usize;
-                }
+    fn resize_buffer(&mut self, num_samples: usize) {
+        let len = num_samples * self.output_format.channel_count as usize;
+        match &mut self.buffer {
+            SampleBuffer::I16(buf) => buf.resize(len, 0),
+            SampleBuffer::U16(buf) => buf.resize(len, 0),
+            SampleBuffer::F32(buf) => buf.resize(len, 0.0),
+        };
+    }
+
+    // advance to the next division in the same order as PT's end-of-row
+    // handling, which produces its quirks when effects are combined: a
+    // pattern loop jump is applied first, then a pending Bxx/Dxx moves on to
+    // the next position at the break row (reset by the loop jump)
+    fn next_division(&mut self) {
+        let state = &mut self.state;
+        state.cur_division += 1;
+
+        if state.pattern_delay > 0 {
+            state.delay_repeat = state.pattern_delay;
+            state.pattern_delay = 0;
+        }
+        if state.delay_repeat > 0 {
+            state.delay_repeat -= 1;
+            if state.delay_repeat > 0 {
+                state.cur_division -= 1;
             }
         }
+
+        if state.break_flag {
+            state.break_flag = false;
+            state.cur_division = state.break_row as usize;
+            state.break_row = 0;
+        }
+
+        if state.cur_division >= 64 || state.position_jump {
+            self.next_position();
+        }
     }
 
-    fn calc_output_samples(&mut self) {
+    fn next_position(&mut self) {
+        let state = &mut self.state;
+        state.cur_division = state.break_row as usize;
+        state.break_row = 0;
+        state.position_jump = false;
+
+        let jumped = state.jump_target.is_some();
+        let mut next = match state.jump_target.take() {
+            Some(pos) => (pos & 0x7f) as usize,
+            None => state.sequence_pos + 1,
+        };
+        let song_length = self.pt_mod.sequence().len();
+        if next >= song_length && self.options.loop_song {
+            next = self.pt_mod.restart_position() as usize;
+        } else if jumped && !self.options.loop_song && state.is_visited(next, state.cur_division) {
+            // jumping back to an already played division loops the song
+            next = song_length;
+        }
+
+        state.sequence_pos = next;
+        if next < song_length {
+            state.cur_pattern = self.pt_mod.sequence()[next] as usize;
+        }
+    }
+
+    // output samples for the next tick, carrying the fraction over to the
+    // following ticks so that the song does not drift
+    fn samples_per_tick(&mut self) -> usize {
+        let tick_duration = if self.options.cia_timing {
+            let (cf, cia_tempo) = match self.clock_freq {
+                ClockFreq::Pal => (CLOCK_FREQ_PAL, CIA_TEMPO_PAL),
+                ClockFreq::Ntsc => (CLOCK_FREQ_NTSC, CIA_TEMPO_NTSC),
+            };
+            // the timer value is the tempo constant divided by the BPM
+            let divisor = cia_tempo * 24 / self.state.ticks_per_min as u64;
+            divisor as f64 * 10.0 / cf
+        } else {
+            60.0 / self.state.ticks_per_min as f64
+        };
+        let exact = self.output_format.sample_rate as f64 * tick_duration + self.state.sample_frac;
+        let samples = exact.floor();
+        self.state.sample_frac = exact - samples;
+        samples as usize
+    }
+
+    // Gain model: a channel playing a full scale sample at volume 64 is 1.0
+    // before panning. The automatic headroom keeps the mix within full scale
+    // with the default panning: mono output is divided by the number of
+    // channels, stereo by the channels on one side (2 for 4 channels). The
+    // master gain is applied on top, the clipping option decides what
+    // happens above full scale.
+    fn calc_output_samples(&mut self, samples_per_tick: usize) {
         let advances = self.calc_advances();
 
-        let samples_per_tick = (self.output_format.sample_rate as f32 * 60.0
-            / self.state.ticks_per_min as f32)
-            .floor() as usize;
+        let num_input_channels = self.state.channels.len();
+        let num_output_channels = self.output_format.channel_count as usize;
+        let gains: Vec<_> = self
+            .state
+            .channels
+            .iter()
+            .map(|cs| pan_gains(cs.pan, self.options.stereo_separation))
+            .collect();
+        let headroom = if num_output_channels == 1 {
+            num_input_channels
+        } else {
+            num_input_channels.div_ceil(2)
+        };
+        let gain = self.options.master_gain / (CHANNEL_FULL_SCALE * headroom as f64);
+
         for idx in 0..samples_per_tick {
-            if self.output_format.channel_count == 1 {
-                let mut val = 0.0;
-                let num_input_channels = self.state.channels.len();
-                for c in 0..num_input_channels {
-                    val += self.next_sample(c, advances[c]);
-                }
-                val /= (num_input_channels / 2) as f64;
-
-                match &mut self.buffer {
-                    SampleBuffer::I16(buf) => buf[idx] = val.floor() as i16,
-                    SampleBuffer::U16(buf) => buf[idx] = (val.floor() + 16384.0) as u16,
-                    SampleBuffer::F32(buf) => {
-                        buf[idx] = (val / 16384.0) as f32;
-                    }
-                };
-            } else if self.output_format.channel_count == 2 {
-                let mut val_l = 0.0;
-                let mut val_r = 0.0;
-                let num_input_channels = self.state.channels.len();
-                for c in 0..num_input_channels {
-                    if c % 4 == 0 || c % 4 == 3 {
-                        val_l += self.next_sample(c, advances[c]);
-                    } else {
-                        val_r += self.next_sample(c, advances[c]);
-                    }
-                    val_l /= (num_input_channels / 4) as f64;
-                    val_r /= (num_input_channels / 4) as f64;
+            let mut frame = [0.0; 2];
+            for (c, advance) in advances.iter().enumerate() {
+                let val = self.next_sample(c, *advance) * gain;
+                if num_output_channels == 1 {
+                    frame[0] += val;
+                } else {
+                    frame[0] += val * gains[c].0;
+                    frame[1] += val * gains[c].1;
                 }
+            }
+            let frame = &mut frame[..num_output_channels];
 
-                match &mut self.buffer {
-                    SampleBuffer::I16(buf) => buf[2 * idx] = val_l.floor() as i16,
-                    SampleBuffer::U16(buf) => buf[2 * idx] = (val_l.floor() + 16384.0) as u16,
-                    SampleBuffer::F32(buf) => {
-                        buf[2 * idx] = (val_l / 16384.0) as f32;
+            for (val, filter) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                *val = filter.process(*val, self.state.led_filter);
+            }
+            match self.options.clipping {
+                Clipping::Hard => {}
+                Clipping::Soft => frame
+                    .iter_mut()
+                    .for_each(|val| *val = output::soft_clip(*val)),
+                Clipping::Limiter => {
+                    if let Some(limiter) = &mut self.limiter {
+                        limiter.process(frame);
                     }
-                };
+                }
+            }
 
-                match &mut self.buffer {
-                    SampleBuffer::I16(buf) => buf[2 * idx + 1] = val_r.floor() as i16,
-                    SampleBuffer::U16(buf) => buf[2 * idx + 1] = (val_r.floor() + 16384.0) as u16,
-                    SampleBuffer::F32(buf) => {
-                        buf[2 * idx + 1] = (val_r / 16384.0) as f32;
+            let pos = idx * num_output_channels;
+            match &mut self.buffer {
+                SampleBuffer::I16(buf) => {
+                    for (out, val) in buf[pos..].iter_mut().zip(frame.iter()) {
+                        *out = output::to_i16(*val);
                     }
-                };
-            }
+                }
+                SampleBuffer::U16(buf) => {
+                    for (out, val) in buf[pos..].iter_mut().zip(frame.iter()) {
+                        *out = output::to_u16(*val);
+                    }
+                }
+                SampleBuffer::F32(buf) => {
+                    for (out, val) in buf[pos..].iter_mut().zip(frame.iter()) {
+                        *out = output::to_f32(*val);
+                    }
+                }
+            };
         }
     }
 
     fn next_sample(&mut self, channel_no: usize, advance: f64) -> f64 {
         let channel = &mut self.state.channels[channel_no];
 
+        if let Some(paula) = &mut self.paula {
+            let samples = &self.pt_mod.samples;
+            let funk_data = &self.funk_data;
+            let sample = channel.sample_no.map(|sample_no| {
+                let sample_idx = sample_no as usize - 1;
+                let sample = &samples[sample_idx];
+                let data = funk_data[sample_idx].as_deref().unwrap_or(&sample.data);
+                (sample, data)
+            });
+            return paula.next_sample(channel_no, channel, sample);
+        }
+
         if channel.sample_no.is_none() {
             return 0.0;
         }
 
-        let sample = &self.pt_mod.samples[channel.sample_no.unwrap() as usize - 1];
+        let sample_idx = channel.sample_no.unwrap() as usize - 1;
+        let sample = &self.pt_mod.samples[sample_idx];
+        let data = match &self.funk_data[sample_idx] {
+            Some(data) => data,
+            None => &sample.data,
+        };
 
-        let offset_int = channel.offset.floor() as usize;
-        let val = if offset_int < sample.length as usize {
-            sample.data[offset_int] as f64 * channel.volume as f64
-        } else {
-            0.0
+        let view = SampleView {
+            data,
+            end: channel.sample_end as usize,
+            repeat: if sample.repeat_length > 2 {
+                let repeat_offset = sample.repeat_offset as usize;
+                Some((repeat_offset, repeat_offset + sample.repeat_length as usize))
+            } else {
+                None
+            },
+            in_loop: channel.in_loop,
         };
+        let val = view.interpolate(self.options.interpolation, channel.offset, advance)
+            * channel.output_volume as f64;
 
         channel.offset += advance;
 
         if sample.repeat_length > 2 {
-            // can loop
-            if channel.in_loop {
-                let mut overflow =
-                    channel.offset - (sample.repeat_offset + sample.repeat_length) as f64;
-                while overflow > 0.0 {
-                    channel.offset = sample.repeat_offset as f64 + overflow;
-                    overflow =
-                        channel.offset - (sample.repeat_offset + sample.repeat_length) as f64;
-                }
+            // can loop, the end of the first pass leads into the loop
+            let loop_end = (sample.repeat_offset + sample.repeat_length) as f64;
+            let mut end = if channel.in_loop {
+                loop_end
             } else {
-                // enter loop
-                let mut overflow = channel.offset - sample.length as f64;
+                channel.sample_end as f64
+            };
+            while channel.offset >= end {
+                channel.offset = sample.repeat_offset as f64 + channel.offset - end;
                 channel.in_loop = true;
-                while overflow > 0.0 {
-                    channel.offset = sample.repeat_offset as f64 + overflow;
-                    overflow =
-                        channel.offset - (sample.repeat_offset + sample.repeat_length) as f64;
-                }
+                end = loop_end;
             }
         }
 
         val
     }
 
-    fn update_division(&mut self) {
-        let pattern = &self.pt_mod.patterns[self.state.cur_pattern];
+    fn update_division(&mut self) -> Result<(), PlayError> {
+        let pattern = match self.pt_mod.patterns.get(self.state.cur_pattern) {
+            Some(pattern) => pattern,
+            None => {
+                return Err(PlayError::PatternIndexOutOfRange {
+                    position: self.state.sequence_pos,
+                    pattern: self.state.cur_pattern,
+                });
+            }
+        };
         let division = &pattern.divisions[self.state.cur_division];
+        self.state
+            .set_visited(self.state.sequence_pos, self.state.cur_division);
 
         for (idx, cd) in division.channel_data.iter().enumerate() {
-            if cd.sample > 0 {
-                // new note
-                let cs = &mut self.state.channels[idx];
-                let vol = self.pt_mod.samples[cd.sample as usize - 1].volume as u16;
-
-                // handle effects
-                match cd.effect {
-                    Effect::Normal {
-                        effect_type,
-                        param1,
-                        param2,
-                    } => {
-                        match effect_type {
-                            EffectType::SlideUp => {
-                                cs.reset(cd.sample, vol, cd.period);
-                                cs.period_diff = (param1 * 16 + param2) as i16;
-                            }
-                            EffectType::SlideDown => {
-                                cs.reset(cd.sample, vol, cd.period);
-                                cs.period_diff = 0 - (param1 * 16 + param2) as i16;
-                            }
-                            EffectType::SlideToNote => {
-                                // do not set the new note directly, keep the last one
-                                cs.period_target = Some(cd.period);
-                                if cd.period < cs.period {
-                                    cs.period_diff = 0 - (param1 * 16 + param2) as i16;
-                                } else {
-                                    cs.period_diff = (param1 * 16 + param2) as i16;
-                                }
-                            }
-                            EffectType::SetVolume => {
-                                cs.reset(cd.sample, vol, cd.period);
-                                cs.volume = (param1 * 16 + param2) as u16;
-                            }
-                            EffectType::SetSpeed => {
-                                let speed_val = param1 * 16 + param2;
-                                if speed_val == 0 {
-                                    // ignore
-                                } else if speed_val >= 1 && speed_val <= 32 {
-                                    self.state.ticks_per_div = speed_val;
-                                } else {
-                                    self.state.ticks_per_min = 4 * 6 * speed_val as u16;
-                                }
-                            }
-                            _ => {
-                                cs.reset(cd.sample, vol, cd.period);
-                            }
-                        }
+            let command = if self.soundtracker_effects {
+                Command::from_soundtracker(cd.effect)
+            } else {
+                Command::from(cd.effect)
+            };
+            self.state.update_flow(idx, command);
+
+            if cd.sample as usize > self.pt_mod.samples.len() {
+                return Err(PlayError::SampleIndexOutOfRange {
+                    pattern: self.state.cur_pattern,
+                    division: self.state.cur_division,
+                    channel: idx,
+                    sample: cd.sample,
+                });
+            }
+            let cs = &mut self.state.channels[idx];
+            cs.clear_division_effects();
+
+            // a sample number selects the sample for the next note and resets
+            // the volume, a note without one reuses the last sample
+            let delayed = matches!(command, Command::NoteDelay(tick) if tick > 0);
+            if cd.sample > 0 && !delayed {
+                cs.load_sample(cd.sample, &self.pt_mod.samples[cd.sample as usize - 1]);
+            }
+            if cd.period > 0 {
+                match command {
+                    // the note is the portamento target, or triggered by the effect
+                    Command::TonePorta(_)
+                    | Command::TonePortaVolumeSlide { .. }
+                    | Command::SampleOffset(_) => {}
+                    Command::NoteDelay(tick) if tick > 0 => {}
+                    Command::SetFinetune(finetune) => {
+                        // replaces the sample's finetune for this note
+                        cs.finetune = finetune;
+                        cs.play_note(cd.period);
                     }
-                    Effect::Extended {
-                        effect_type: _,
-                        param: _,
-                    } => {
-                        cs.reset(cd.sample, vol, cd.period);
+                    _ => cs.play_note(cd.period),
+                }
+            }
+
+            match command {
+                Command::Arpeggio { x, y } => {
+                    cs.arpeggio = Some((x, y));
+                }
+                Command::PortaUp(speed) => {
+                    if speed > 0 {
+                        cs.porta_up_speed = speed;
+                    }
+                    cs.period_diff = -(cs.porta_up_speed as i16);
+                }
+                Command::PortaDown(speed) => {
+                    if speed > 0 {
+                        cs.porta_down_speed = speed;
+                    }
+                    cs.period_diff = cs.porta_down_speed as i16;
+                }
+                Command::TonePorta(speed) => {
+                    if speed > 0 {
+                        cs.tone_porta_speed = speed;
+                    }
+                    cs.tone_porta(cd.period);
+                }
+                Command::Vibrato { speed, depth } => {
+                    cs.vibrato.set_params(speed, depth);
+                    cs.vibrato_active = true;
+                }
+                Command::TonePortaVolumeSlide { up, down } => {
+                    cs.tone_porta(cd.period);
+                    cs.volume_diff = volume_slide(up, down);
+                }
+                Command::VibratoVolumeSlide { up, down } => {
+                    cs.vibrato_active = true;
+                    cs.volume_diff = volume_slide(up, down);
+                }
+                Command::Tremolo { speed, depth } => {
+                    cs.tremolo.set_params(speed, depth);
+                    cs.tremolo_active = true;
+                }
+                Command::SampleOffset(param) => {
+                    // with a note PT applies the offset twice, as in pt2-clone:
+                    // mt_SetRegs runs it before triggering the note and
+                    // mt_SetPeriod runs it again, which only moves the start
+                    // used by later notes; without a note it runs once. This
+                    // is the reverse of "twice without a note", on purpose.
+                    cs.sample_offset(param);
+                    if cd.period > 0 {
+                        cs.play_note(cd.period);
+                        cs.sample_offset(param);
+                    }
+                }
+                Command::VolumeSlide { up, down } => {
+                    cs.volume_diff = volume_slide(up, down);
+                }
+                Command::SetVolume(volume) => {
+                    cs.volume = (volume as u16).min(64);
+                }
+                Command::SetSpeed(speed) if speed > 0 => {
+                    self.state.ticks_per_div = speed;
+                }
+                Command::SetTempo(bpm) => {
+                    self.state.ticks_per_min = 4 * 6 * bpm as u16;
+                }
+                Command::SetFilter(x) => {
+                    // bit 0 turns the filter off, like the LED itself
+                    self.state.led_filter = x & 1 == 0;
+                }
+                Command::FinePortaUp(x) => {
+                    cs.period = (cs.period as i32 - x as i32).max(PERIOD_MIN) as u16;
+                }
+                Command::FinePortaDown(x) => {
+                    cs.period = (cs.period as i32 + x as i32).min(PERIOD_MAX) as u16;
+                }
+                Command::Glissando(x) => {
+                    cs.glissando = x != 0;
+                }
+                Command::SetVibratoWaveform(param) => {
+                    cs.vibrato.set_waveform(param);
+                }
+                Command::SetTremoloWaveform(param) => {
+                    cs.tremolo.set_waveform(param);
+                }
+                Command::Retrigger(interval) => {
+                    cs.retrigger = interval;
+                    // without a note, PT restarts the sample on the first tick too
+                    if interval > 0 && cd.period == 0 {
+                        cs.restart();
+                    }
+                }
+                Command::FineVolumeSlideUp(x) => {
+                    cs.volume = (cs.volume + x as u16).min(64);
+                }
+                Command::FineVolumeSlideDown(x) => {
+                    cs.volume = cs.volume.saturating_sub(x as u16);
+                }
+                Command::NoteCut(tick) => {
+                    cs.note_cut = Some(tick);
+                }
+                Command::NoteDelay(tick) if tick > 0 => {
+                    cs.note_delay = Some((tick, cd.sample, cd.period));
+                }
+                Command::SetPanning(pan) if self.options.pan_effects => {
+                    cs.pan = pan;
+                }
+                Command::SetPanningCoarse(pan) if self.options.pan_effects => {
+                    cs.pan = pan * 17;
+                }
+                Command::InvertLoop(speed) => {
+                    cs.funk_speed = speed;
+                    if speed > 0 {
+                        update_funk(cs, &self.pt_mod.samples, &mut self.funk_data);
                     }
                 }
+                _ => {}
             }
         }
+        Ok(())
     }
 
     fn update_tick(&mut self) {
+        // PT runs the per-tick effects on the first tick of a repeated division,
+        // the arpeggio still starts with the base note
+        let tick = if self.state.delay_repeat > 0 {
+            self.state.cur_tick.max(1)
+        } else {
+            self.state.cur_tick
+        };
+        let cur_tick = self.state.cur_tick;
+        let rng = &mut self.state.rng;
         for channel in self.state.channels.iter_mut() {
-            match channel.period_target {
-                Some(period_target) => {
-                    if channel.period_diff < 0 {
-                        channel.period = ((channel.period as i16 + channel.period_diff) as u16)
-                            .max(period_target);
-                    } else {
-                        channel.period = ((channel.period as i16 + channel.period_diff) as u16)
-                            .min(period_target);
+            if tick > 0 {
+                update_funk(channel, &self.pt_mod.samples, &mut self.funk_data);
+            }
+
+            // a delay longer than the division never triggers the note
+            if let Some((delay, sample_no, period)) = channel.note_delay {
+                if delay == cur_tick {
+                    if sample_no > 0 {
+                        let sample = &self.pt_mod.samples[sample_no as usize - 1];
+                        channel.load_sample(sample_no, sample);
+                    }
+                    if period > 0 {
+                        channel.play_note(period);
+                    }
+                }
+            }
+            if channel.retrigger > 0 && cur_tick > 0 && cur_tick.is_multiple_of(channel.retrigger) {
+                channel.restart();
+            }
+            if channel.note_cut == Some(cur_tick) {
+                channel.volume = 0;
+            }
+
+            // slides start on the second tick of a division
+            if tick > 0 {
+                let period = channel.period as i32 + channel.period_diff as i32;
+                let period = if channel.tone_porta_active {
+                    match channel.period_target {
+                        Some(target) if channel.period_diff < 0 => period.max(target as i32),
+                        Some(target) => period.min(target as i32),
+                        None => channel.period as i32,
                     }
+                } else if channel.period_diff < 0 {
+                    // PT shares these limits between normal and fine slides
+                    period.max(PERIOD_MIN)
+                } else if channel.period_diff > 0 {
+                    period.min(PERIOD_MAX)
+                } else {
+                    period
+                };
+                channel.period = period as u16;
+                if channel.period_target == Some(channel.period) {
+                    // target reached, a later 3xx without a note does nothing
+                    channel.period_target = None;
                 }
-                None => {
-                    channel.period = (channel.period as i16 + channel.period_diff) as u16;
+
+                let volume = channel.volume as i32 + channel.volume_diff as i32;
+                channel.volume = volume.clamp(0, 64) as u16;
+            }
+
+            channel.output_period = match channel.arpeggio {
+                Some((x, _)) if cur_tick % 3 == 1 => {
+                    arpeggio_period(channel.finetune, channel.period, x)
+                }
+                Some((_, y)) if cur_tick % 3 == 2 => {
+                    arpeggio_period(channel.finetune, channel.period, y)
                 }
+                _ => channel.period,
+            };
+            if channel.glissando && channel.tone_porta_active {
+                // snap to the next lower note, as the arpeggio lookup does
+                channel.output_period = arpeggio_period(channel.finetune, channel.period, 0);
+            }
+            channel.output_volume = if channel.output_period > 0 {
+                channel.volume
+            } else {
+                // the table's terminating zero, Paula plays nothing at period 0
+                0
+            };
+
+            // modulation starts on the second tick and leaves the base values alone
+            if tick > 0 && channel.vibrato_active {
+                let delta = channel
+                    .vibrato
+                    .delta(7, channel.vibrato.pos, xorshift(rng) as u8);
+                channel.output_period = (channel.period as i32 + delta).max(0) as u16;
+                channel.vibrato.advance();
+            }
+            if tick > 0 && channel.tremolo_active {
+                let delta = channel
+                    .tremolo
+                    .delta(6, channel.vibrato.pos, xorshift(rng) as u8);
+                channel.output_volume = (channel.volume as i32 + delta).clamp(0, 64) as u16;
+                channel.tremolo.advance();
             }
         }
     }
@@ -326,16 +809,79 @@ impl ProtrackerPlayer {
                 ClockFreq::Pal => CLOCK_FREQ_PAL,
                 ClockFreq::Ntsc => CLOCK_FREQ_NTSC,
             };
-            let samples_per_sec = cf / channel.period as f64;
+            if channel.output_period == 0 {
+                advances.push(0.0);
+                continue;
+            }
+            let samples_per_sec = cf / channel.output_period as f64;
             advances.push(samples_per_sec / (self.output_format.sample_rate * 2) as f64);
         }
         advances
     }
 }
 
+// EFx: each time the funk counter overflows, invert the next byte of the
+// sample's loop in the player's copy of the sample data
+fn update_funk(channel: &mut ChannelState, samples: &[Sample], funk_data: &mut [Option<Vec<i8>>]) {
+    let sample_no = match channel.sample_no {
+        Some(sample_no) if channel.funk_speed > 0 => sample_no as usize,
+        _ => return,
+    };
+    channel.funk_offset += FUNK_TABLE[channel.funk_speed as usize & 0x0f];
+    if channel.funk_offset >= 128 {
+        channel.funk_offset = 0;
+        let sample = &samples[sample_no - 1];
+        // one-shot samples have a one-word loop at the start
+        let loop_end = sample.repeat_offset + sample.repeat_length.max(2);
+        channel.funk_pos += 1;
+        if channel.funk_pos >= loop_end {
+            channel.funk_pos = sample.repeat_offset;
+        }
+        let data = funk_data[sample_no - 1].get_or_insert_with(|| sample.data.clone());
+        if let Some(v) = data.get_mut(channel.funk_pos as usize) {
+            *v = !*v;
+        }
+    }
+}
+
+// per-tick volume change of Axy, 5xy and 6xy; PT ignores y when x is set
+fn volume_slide(up: u8, down: u8) -> i16 {
+    if up > 0 {
+        up as i16
+    } else {
+        -(down as i16)
+    }
+}
+
+fn xorshift(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+// Amiga channel layout: LRRL, repeated for every group of 4 channels
+fn is_left_channel(channel_no: usize) -> bool {
+    matches!(channel_no % 4, 0 | 3)
+}
+
+// left and right gain for a pan position, narrowed towards the centre by the
+// stereo separation in percent
+fn pan_gains(pan: u8, separation: u8) -> (f64, f64) {
+    let separation = separation.min(100) as f64 / 100.0;
+    let pos = 0.5 + (pan as f64 / 255.0 - 0.5) * separation;
+    (1.0 - pos, pos)
+}
+
 impl PlayerState {
     fn default(num_channels: usize) -> PlayerState {
-        let channels: Vec<_> = (0..num_channels).map(|_| ChannelState::default()).collect();
+        let channels: Vec<_> = (0..num_channels)
+            .map(|c| {
+                let mut cs = ChannelState::default();
+                cs.pan = if is_left_channel(c) { 0 } else { 255 };
+                cs
+            })
+            .collect();
 
         PlayerState {
             sequence_pos: 0,
@@ -344,7 +890,76 @@ impl PlayerState {
             cur_tick: 0,
             ticks_per_min: 4 * 6 * 125,
             ticks_per_div: 6,
+            sample_frac: 0.0,
             channels,
+            rng: 0x2545_f491,
+            break_row: 0,
+            break_flag: false,
+            position_jump: false,
+            jump_target: None,
+            pattern_delay: 0,
+            delay_repeat: 0,
+            led_filter: false,
+            visited: vec![0; 128],
+        }
+    }
+
+    // position jump, pattern break, pattern loop and pattern delay, applied
+    // at the end of the division by `next_division`
+    fn update_flow(&mut self, channel_no: usize, command: Command) {
+        match command {
+            Command::PositionJump(pos) => {
+                self.jump_target = Some(pos);
+                self.break_row = 0;
+                self.position_jump = true;
+            }
+            Command::PatternBreak { row } => {
+                self.break_row = if row > 63 { 0 } else { row };
+                self.position_jump = true;
+            }
+            Command::Raw(effect) => {
+                // pattern break with a non-BCD parameter, still read as x * 10 + y
+                let (t, x, y) = effect.to_nibbles();
+                if t == 0xd {
+                    let row = x * 10 + y;
+                    self.break_row = if row > 63 { 0 } else { row };
+                    self.position_jump = true;
+                }
+            }
+            Command::PatternLoop(0) => {
+                self.channels[channel_no].loop_row = self.cur_division as u8;
+            }
+            Command::PatternLoop(count) => {
+                let cs = &mut self.channels[channel_no];
+                if cs.loop_count == 0 {
+                    cs.loop_count = count;
+                } else {
+                    cs.loop_count -= 1;
+                    if cs.loop_count == 0 {
+                        return;
+                    }
+                }
+                self.break_row = cs.loop_row;
+                self.break_flag = true;
+            }
+            // ignored while a division is being repeated
+            Command::PatternDelay(count) if self.delay_repeat == 0 => {
+                self.pattern_delay = count + 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_visited(&mut self, sequence_pos: usize, division: usize) {
+        if let Some(bits) = self.visited.get_mut(sequence_pos) {
+            *bits |= 1 << division;
+        }
+    }
+
+    fn is_visited(&self, sequence_pos: usize, division: usize) -> bool {
+        match self.visited.get(sequence_pos) {
+            Some(bits) => bits & (1 << division) != 0,
+            None => false,
         }
     }
 }
@@ -353,24 +968,1172 @@ impl ChannelState {
     fn default() -> ChannelState {
         ChannelState {
             sample_no: None,
+            instrument: None,
             volume: 0,
             volume_diff: 0,
             offset: 0.0,
+            sample_start: 0,
+            sample_length: 0,
+            offset_memory: 0,
+            sample_end: 0,
             period: 0,
             period_diff: 0,
             period_target: None,
+            tone_porta_active: false,
+            porta_up_speed: 0,
+            porta_down_speed: 0,
+            tone_porta_speed: 0,
+            glissando: false,
+            finetune: 0,
+            arpeggio: None,
+            vibrato: Oscillator::default(),
+            vibrato_active: false,
+            tremolo: Oscillator::default(),
+            tremolo_active: false,
+            note_cut: None,
+            note_delay: None,
+            retrigger: 0,
+            output_period: 0,
+            output_volume: 0,
+            loop_row: 0,
+            loop_count: 0,
+            pan: 128,
+            funk_speed: 0,
+            funk_offset: 0,
+            funk_pos: 0,
             in_loop: false,
         }
     }
 
-    fn reset(&mut self, sample_no: u8, volume: u16, period: u16) {
-        self.sample_no = Some(sample_no);
-        self.volume = volume;
+    // reset the effects which only last for one division
+    fn clear_division_effects(&mut self) {
         self.volume_diff = 0;
-        self.offset = 0.0;
-        self.period = period;
         self.period_diff = 0;
-        self.period_target = None;
+        self.tone_porta_active = false;
+        self.arpeggio = None;
+        self.vibrato_active = false;
+        self.tremolo_active = false;
+        self.note_cut = None;
+        self.note_delay = None;
+        self.retrigger = 0;
+    }
+
+    // select the sample for the next note and take over its volume and finetune
+    fn load_sample(&mut self, sample_no: u8, sample: &Sample) {
+        self.instrument = Some(sample_no);
+        self.volume = sample.volume as u16;
+        self.sample_start = 0;
+        // PT plays looped samples up to the loop end only
+        self.sample_length = if sample.repeat_length > 2 {
+            sample.repeat_offset + sample.repeat_length
+        } else {
+            sample.length
+        };
+        self.finetune = sample.finetune;
+        self.funk_pos = sample.repeat_offset;
+    }
+
+    // play a note with the selected sample, `period` is the note at finetune 0
+    fn play_note(&mut self, period: u16) {
+        self.period = note_period(self.finetune, period);
+        self.trigger();
+    }
+
+    // start playing the sample from the current sample start
+    fn trigger(&mut self) {
+        self.restart();
+        self.vibrato.note_on();
+        self.tremolo.note_on();
+    }
+
+    // restart sample playback only, as E9x does
+    fn restart(&mut self) {
+        self.sample_no = self.instrument;
+        self.offset = self.sample_start as f64;
+        self.sample_end = self.sample_start + self.sample_length;
         self.in_loop = false;
     }
+
+    // 9xx: advance the sample start by xx * 256 bytes, or play a single word
+    // if that is past the end (compared as signed words, as PT does)
+    fn sample_offset(&mut self, param: u8) {
+        if param > 0 {
+            self.offset_memory = param;
+        }
+        let offset_words = (self.offset_memory as u16) << 7;
+        if (offset_words as i16) < ((self.sample_length / 2) as u16 as i16) {
+            self.sample_start += offset_words as u32 * 2;
+            self.sample_length -= offset_words as u32 * 2;
+        } else {
+            self.sample_length = 2;
+        }
+    }
+
+    // slide towards `period` (or the previous target if 0) at the last 3xx speed
+    fn tone_porta(&mut self, period: u16) {
+        self.tone_porta_active = true;
+        if period > 0 {
+            self.period_target = Some(tone_porta_period(self.finetune, period));
+        }
+        if let Some(target) = self.period_target {
+            if target < self.period {
+                self.period_diff = -(self.tone_porta_speed as i16);
+            } else {
+                self.period_diff = self.tone_porta_speed as i16;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::protracker::{ChannelData, Division, Effect, Pattern, Sample};
+    use std::f64::consts::PI;
+
+    // module with a looped square wave as sample 1 and empty patterns
+    fn test_mod(num_channels: u8, num_patterns: usize) -> ProtrackerMod {
+        let mut data = vec![64; 32];
+        data.extend(vec![-64; 32]);
+        let square = Sample {
+            name: String::from("square"),
+            finetune: 0,
+            length: 64,
+            volume: 64,
+            repeat_offset: 0,
+            repeat_length: 64,
+            data,
+        };
+        let patterns = (0..num_patterns)
+            .map(|_| Pattern {
+                divisions: (0..64)
+                    .map(|_| Division {
+                        channel_data: (0..num_channels).map(|_| cell(0, 0, 0, 0, 0)).collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        let mut pattern_table = [0; 128];
+        for (pos, pat) in pattern_table.iter_mut().enumerate().take(num_patterns) {
+            *pat = pos as u8;
+        }
+        ProtrackerMod {
+            title: String::from("test"),
+            num_channels,
+            samples: vec![square],
+            song_length: num_patterns as u8,
+            restart_pos: 127,
+            pattern_table,
+            patterns,
+        }
+    }
+
+    fn cell(sample: u8, period: u16, t: u8, x: u8, y: u8) -> ChannelData {
+        ChannelData {
+            sample,
+            period,
+            effect: Effect::from_nibbles(t, x, y),
+        }
+    }
+
+    // module with `rows` in channel 0 of the first divisions
+    fn rows_mod(rows: &[(u8, u16, u8, u8, u8)]) -> ProtrackerMod {
+        let mut pt_mod = test_mod(4, 1);
+        for (division, &(sample, period, t, x, y)) in
+            pt_mod.patterns[0].divisions.iter_mut().zip(rows)
+        {
+            division.channel_data[0] = cell(sample, period, t, x, y);
+        }
+        pt_mod
+    }
+
+    // one-shot sample of 4096 bytes rising by 1 every 16 bytes
+    fn ramp_sample() -> Sample {
+        Sample {
+            name: String::from("ramp"),
+            finetune: 0,
+            length: 4096,
+            volume: 64,
+            repeat_offset: 0,
+            repeat_length: 0,
+            data: (0..4096).map(|i| (i / 16) as i8).collect(),
+        }
+    }
+
+    fn output_format(sample_rate: u32) -> OutputFormat {
+        OutputFormat {
+            sample_rate,
+            sample_format: SampleFormat::I16,
+            channel_count: 2,
+        }
+    }
+
+    #[test]
+    fn test_loop_song() {
+        // 3 patterns at speed 6
+        let num_ticks = 3 * 64 * 6;
+
+        let player = |loop_song| {
+            let mut pt_mod = test_mod(4, 3);
+            pt_mod.restart_pos = 1;
+            let options = PlayerOptions {
+                loop_song,
+                ..PlayerOptions::default()
+            };
+            ProtrackerPlayer::with_options(pt_mod, ClockFreq::Pal, output_format(8000), options)
+                .unwrap()
+        };
+
+        let mut player_once = player(false);
+        for _i in 0..num_ticks {
+            assert!(
+                matches!(player_once.get_samples(), Ok(SampleOutput::I16(buf)) if !buf.is_empty())
+            );
+        }
+        assert!(matches!(player_once.get_samples(), Ok(SampleOutput::I16(buf)) if buf.is_empty()));
+
+        let mut player_loop = player(true);
+        for _i in 0..num_ticks {
+            player_loop.get_samples().unwrap();
+        }
+        assert_eq!(1, player_loop.state.sequence_pos);
+        assert_eq!(1, player_loop.state.cur_pattern);
+        assert!(matches!(player_loop.get_samples(), Ok(SampleOutput::I16(buf)) if !buf.is_empty()));
+    }
+
+    // output periods of channel 0 for each tick of the first `num_ticks` ticks
+    fn rendered_periods(pt_mod: ProtrackerMod, num_ticks: usize) -> Vec<u16> {
+        render_ticks(pt_mod, num_ticks, |cs| cs.output_period)
+    }
+
+    fn rendered_volumes(pt_mod: ProtrackerMod, num_ticks: usize) -> Vec<u16> {
+        render_ticks(pt_mod, num_ticks, |cs| cs.output_volume)
+    }
+
+    fn render_ticks<F>(pt_mod: ProtrackerMod, num_ticks: usize, f: F) -> Vec<u16>
+    where
+        F: Fn(&ChannelState) -> u16,
+    {
+        let mut player = player_after(pt_mod, 0);
+        (0..num_ticks)
+            .map(|_| {
+                player.get_samples().unwrap();
+                f(&player.state.channels[0])
+            })
+            .collect()
+    }
+
+    // whether each of the first `num_ticks` ticks is silent
+    fn silent_ticks(pt_mod: ProtrackerMod, num_ticks: usize) -> Vec<bool> {
+        let mut player = player_after(pt_mod, 0);
+        (0..num_ticks)
+            .map(|_| match player.get_samples() {
+                Ok(SampleOutput::I16(buf)) => buf.iter().all(|v| *v == 0),
+                r => panic!("unexpected output {:?}", r.err()),
+            })
+            .collect()
+    }
+
+    // player for `pt_mod` after playing its first `num_ticks` ticks
+    fn player_after(pt_mod: ProtrackerMod, num_ticks: usize) -> ProtrackerPlayer {
+        let mut player =
+            ProtrackerPlayer::new(pt_mod, ClockFreq::Pal, output_format(8000)).unwrap();
+        for _tick in 0..num_ticks {
+            player.get_samples().unwrap();
+        }
+        player
+    }
+
+    #[test]
+    fn test_arpeggio() {
+        let rows = [(1, 214, 0, 4, 7), (1, 214, 0, 0, 0)];
+        assert_eq!(
+            vec![214, 170, 143, 214, 170, 143, 214, 214, 214],
+            rendered_periods(rows_mod(&rows), 9)
+        );
+
+        // finetune selects the table row, inexact periods snap to a note
+        let mut pt_mod = rows_mod(&[(1, 216, 0, 1, 10)]);
+        pt_mod.samples[0].finetune = -8;
+        assert_eq!(vec![216, 202, 120], rendered_periods(pt_mod, 3));
+    }
+
+    #[test]
+    fn test_arpeggio_overflow() {
+        // past B-3 PT reads the zero after the table row, then the next row
+        let pt_mod = rows_mod(&[(1, 120, 0, 2, 3)]);
+        assert_eq!(vec![120, 0, 850], rendered_periods(pt_mod, 3));
+
+        // a muted tick renders silence
+        let pt_mod = rows_mod(&[(1, 113, 0, 1, 1)]);
+        assert_eq!(vec![false, true, true], silent_ticks(pt_mod, 3));
+    }
+
+    #[test]
+    fn test_vibrato() {
+        let rows = [(1, 428, 0x4, 8, 15), (1, 428, 0x4, 0, 0)];
+        assert_eq!(
+            vec![428, 428, 449, 457, 449, 428, 428, 428, 449, 457, 449, 428],
+            rendered_periods(rows_mod(&rows), 12)
+        );
+
+        // E44: sine without retrigger, the second note continues the cycle
+        let rows = [
+            (1, 428, 0xe, 0x4, 4),
+            (1, 428, 0x4, 8, 15),
+            (1, 428, 0x4, 0, 0),
+        ];
+        let periods = rendered_periods(rows_mod(&rows), 18);
+        assert_eq!(vec![428; 6], periods[0..6]);
+        assert_eq!(vec![428, 428, 449, 457, 449, 428], periods[6..12]);
+        assert_eq!(vec![428, 407, 399, 407, 428, 449], periods[12..18]);
+
+        // square wave
+        let rows = [(1, 428, 0xe, 0x4, 2), (1, 428, 0x4, 0xf, 4)];
+        let periods = rendered_periods(rows_mod(&rows), 12);
+        assert_eq!(vec![428, 435, 435, 435, 421, 421], periods[6..12]);
+    }
+
+    #[test]
+    fn test_tremolo() {
+        let mut pt_mod = rows_mod(&[(1, 428, 0x7, 8, 8), (1, 428, 0x7, 0, 15)]);
+        pt_mod.samples[0].volume = 32;
+        let volumes = rendered_volumes(pt_mod, 12);
+        assert_eq!(vec![32, 32, 54, 63, 54, 32], volumes[0..6]);
+        // clamped to 0..64, the base volume is left alone
+        assert_eq!(vec![32, 32, 64, 64, 64, 32], volumes[6..12]);
+    }
+
+    #[test]
+    fn test_volume_slide() {
+        let volumes = |t, x, y| {
+            let mut pt_mod = rows_mod(&[(1, 428, t, x, y), (1, 428, 0, 0, 0)]);
+            pt_mod.samples[0].volume = 32;
+            rendered_volumes(pt_mod, 7)
+        };
+        assert_eq!(vec![32, 36, 40, 44, 48, 52, 32], volumes(0xa, 4, 0));
+        assert_eq!(vec![32, 29, 26, 23, 20, 17, 32], volumes(0xa, 0, 3));
+        // up wins over down, slides clamp to 0..64
+        assert_eq!(vec![32, 34, 36, 38, 40, 42, 32], volumes(0xa, 2, 3));
+        assert_eq!(vec![32, 47, 62, 64, 64, 64, 32], volumes(0xa, 0xf, 0));
+        assert_eq!(vec![32, 17, 2, 0, 0, 0, 32], volumes(0xa, 0, 0xf));
+    }
+
+    #[test]
+    fn test_combined_volume_slide() {
+        let build = |rows: &[(u8, u16, u8, u8, u8)]| {
+            let mut pt_mod = rows_mod(rows);
+            pt_mod.samples[0].volume = 32;
+            pt_mod
+        };
+
+        // 5xy continues the 3xx portamento
+        let rows = [(1, 428, 0, 0, 0), (1, 214, 0x3, 0, 8), (1, 0, 0x5, 2, 0)];
+        let periods = rendered_periods(build(&rows), 18);
+        assert_eq!(vec![428, 420, 412, 404, 396, 388], periods[6..12]);
+        assert_eq!(vec![388, 380, 372, 364, 356, 348], periods[12..18]);
+        let volumes = rendered_volumes(build(&rows), 18);
+        assert_eq!(vec![32, 34, 36, 38, 40, 42], volumes[12..18]);
+
+        // 6xy continues the vibrato
+        let rows = [(1, 428, 0x4, 8, 15), (1, 428, 0x6, 0, 4)];
+        let periods = rendered_periods(build(&rows), 12);
+        assert_eq!(vec![428, 428, 449, 457, 449, 428], periods[6..12]);
+        let volumes = rendered_volumes(build(&rows), 12);
+        assert_eq!(vec![32, 28, 24, 20, 16, 12], volumes[6..12]);
+    }
+
+    // (sequence position, division) of the first `num_divisions` divisions played
+    fn played_divisions(pt_mod: ProtrackerMod, num_divisions: usize) -> Vec<(usize, usize)> {
+        let mut player = player_after(pt_mod, 0);
+        let mut divisions = vec![];
+        while divisions.len() < num_divisions {
+            let pos = (player.state.sequence_pos, player.state.cur_division);
+            let tick = player.state.cur_tick;
+            match player.get_samples() {
+                Ok(SampleOutput::I16(buf)) if !buf.is_empty() => {
+                    if tick == 0 {
+                        divisions.push(pos);
+                    }
+                }
+                _ => break,
+            }
+        }
+        divisions
+    }
+
+    #[test]
+    fn test_position_jump_pattern_break() {
+        let flow = |cells: &[(usize, usize, u8, u8, u8)]| {
+            let mut pt_mod = test_mod(4, 3);
+            for &(division, channel, t, x, y) in cells {
+                pt_mod.patterns[0].divisions[division].channel_data[channel] = cell(0, 0, t, x, y);
+            }
+            played_divisions(pt_mod, 3)
+        };
+
+        assert_eq!(vec![(0, 0), (2, 0), (2, 1)], flow(&[(0, 0, 0xb, 0, 2)]));
+        assert_eq!(vec![(0, 0), (1, 16), (1, 17)], flow(&[(0, 1, 0xd, 1, 6)]));
+        // rows past the pattern end restart at 0, non-BCD rows are x * 10 + y
+        assert_eq!(vec![(0, 0), (1, 0), (1, 1)], flow(&[(0, 1, 0xd, 9, 9)]));
+        assert_eq!(vec![(0, 0), (1, 20), (1, 21)], flow(&[(0, 1, 0xd, 1, 0xa)]));
+        // B+D jumps to the row of the new position, unless B comes later
+        assert_eq!(
+            vec![(0, 0), (2, 5), (2, 6)],
+            flow(&[(0, 0, 0xb, 0, 2), (0, 1, 0xd, 0, 5)])
+        );
+        assert_eq!(
+            vec![(0, 0), (2, 0), (2, 1)],
+            flow(&[(0, 0, 0xd, 0, 5), (0, 1, 0xb, 0, 2)])
+        );
+        // jumping past the song end or back to a played division ends the song
+        assert_eq!(vec![(0, 0)], flow(&[(0, 0, 0xb, 0, 3)]));
+        assert_eq!(vec![(0, 0), (0, 1)], flow(&[(1, 0, 0xb, 0, 0)]));
+    }
+
+    #[test]
+    fn test_pattern_loop() {
+        let rows = [(0, 0, 0, 0, 0), (0, 0, 0xe, 0x6, 0), (0, 0, 0xe, 0x6, 2)];
+        let pt_mod = rows_mod(&rows);
+        assert_eq!(
+            vec![
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (0, 1),
+                (0, 2),
+                (0, 1),
+                (0, 2),
+                (0, 3)
+            ],
+            played_divisions(pt_mod, 8)
+        );
+
+        // the loop start is kept per channel, E6x without E60 loops from the
+        // last start set in that channel
+        let mut pt_mod = test_mod(4, 2);
+        pt_mod.patterns[0].divisions[1].channel_data[1] = cell(0, 0, 0xe, 0x6, 0);
+        pt_mod.patterns[0].divisions[2].channel_data[1] = cell(0, 0, 0xe, 0x6, 1);
+        pt_mod.patterns[0].divisions[3].channel_data[0] = cell(0, 0, 0xe, 0x6, 1);
+        assert_eq!(
+            vec![
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (0, 1),
+                (0, 2),
+                (0, 3),
+                (0, 0),
+                (0, 1)
+            ],
+            played_divisions(pt_mod, 8)
+        );
+
+        // with Dxx on the same division the loop jump clears the break row,
+        // so the next pattern starts at 0 while looping and at xx afterwards
+        let mut pt_mod = test_mod(4, 2);
+        pt_mod.song_length = 4;
+        pt_mod.pattern_table[2] = 0;
+        pt_mod.pattern_table[3] = 1;
+        let divisions = &mut pt_mod.patterns[0].divisions;
+        divisions[0].channel_data[0] = cell(0, 0, 0xe, 0x6, 0);
+        divisions[1].channel_data[0] = cell(0, 0, 0xe, 0x6, 1);
+        divisions[1].channel_data[1] = cell(0, 0, 0xd, 1, 0);
+        pt_mod.patterns[1].divisions[0].channel_data[0] = cell(0, 0, 0xd, 0, 0);
+        assert_eq!(
+            vec![(0, 0), (0, 1), (1, 0), (2, 0), (2, 1), (3, 10)],
+            played_divisions(pt_mod, 6)
+        );
+    }
+
+    #[test]
+    fn test_pattern_delay() {
+        let build = || {
+            let mut pt_mod = rows_mod(&[(1, 428, 0xa, 2, 0)]);
+            pt_mod.samples[0].volume = 32;
+            let divisions = &mut pt_mod.patterns[0].divisions;
+            divisions[0].channel_data[1] = cell(0, 0, 0xe, 0xe, 2);
+            divisions[1].channel_data[1] = cell(0, 0, 0xe, 0xe, 0);
+            pt_mod
+        };
+        assert_eq!(
+            vec![(0, 0), (0, 0), (0, 0), (0, 1), (0, 2)],
+            played_divisions(build(), 5)
+        );
+
+        // the repeated divisions slide on their first tick as well
+        let volumes = rendered_volumes(build(), 19);
+        assert_eq!(vec![32, 34, 36, 38, 40, 42], volumes[0..6]);
+        assert_eq!(vec![44, 46, 48, 50, 52, 54], volumes[6..12]);
+        assert_eq!(vec![64, 64], volumes[17..19]);
+
+        // but the arpeggio starts over with the base note
+        let mut pt_mod = rows_mod(&[(1, 214, 0, 4, 7)]);
+        pt_mod.patterns[0].divisions[0].channel_data[1] = cell(0, 0, 0xe, 0xe, 1);
+        assert_eq!(
+            vec![214, 170, 143, 214, 170, 143],
+            rendered_periods(pt_mod, 12)[6..12]
+        );
+    }
+
+    #[test]
+    fn test_sample_offset() {
+        let build = |repeat_offset, repeat_length, rows: &[(u8, u16, u8, u8, u8)]| {
+            let mut pt_mod = rows_mod(rows);
+            pt_mod.samples[0] = Sample {
+                repeat_offset,
+                repeat_length,
+                ..ramp_sample()
+            };
+            pt_mod
+        };
+
+        // with a note the sample plays from xx * 256, the start used by later
+        // notes is moved twice. Deliberately not the "twice without a note"
+        // the quirk is often described as: PT 2.3 and pt2-clone double it on
+        // notes, see the comment on Command::SampleOffset.
+        let rows = [(1, 428, 0x9, 0, 2), (1, 0, 0x9, 0, 0)];
+        let player = player_after(build(0, 0, &rows), 1);
+        let cs = &player.state.channels[0];
+        assert!(cs.offset > 512.0 && cs.offset < 700.0);
+        assert_eq!(
+            (1024, 3072, 4096),
+            (cs.sample_start, cs.sample_length, cs.sample_end)
+        );
+
+        // without a note it is applied once, 900 reuses the last offset
+        let player = player_after(build(0, 0, &rows), 7);
+        let cs = &player.state.channels[0];
+        assert_eq!((512, 3584), (cs.sample_start, cs.sample_length));
+
+        // past the end of a one-shot sample: one word from the start, then silence
+        let rows = [(1, 428, 0x9, 1, 0)];
+        let player = player_after(build(0, 0, &rows), 1);
+        assert_eq!(2, player.state.channels[0].sample_end);
+        assert!(silent_ticks(build(0, 0, &rows), 2)[1]);
+
+        // past the end of a looped sample: one word, then the loop
+        let player = player_after(build(2048, 1024, &[(1, 428, 0x9, 0xf, 0)]), 1);
+        let cs = &player.state.channels[0];
+        assert!(cs.in_loop);
+        assert!(cs.offset >= 2048.0 && cs.offset < 3072.0);
+
+        // the loop end limits the offset range of looped samples
+        let player = player_after(build(0, 1024, &[(1, 428, 0x9, 0, 4)]), 1);
+        assert_eq!(2, player.state.channels[0].sample_end);
+    }
+
+    #[test]
+    fn test_note_cut() {
+        let volumes = |x| rendered_volumes(rows_mod(&[(1, 428, 0xe, 0xc, x)]), 6);
+        assert_eq!(vec![64, 64, 0, 0, 0, 0], volumes(2));
+        assert_eq!(vec![0; 6], volumes(0));
+        assert_eq!(vec![64; 6], volumes(6));
+    }
+
+    #[test]
+    fn test_note_delay() {
+        let build = |x| rows_mod(&[(1, 428, 0, 0, 0), (1, 214, 0xe, 0xd, x)]);
+        let periods = rendered_periods(build(2), 12);
+        assert_eq!(vec![428, 428, 214, 214, 214, 214], periods[6..12]);
+        let periods = rendered_periods(build(0), 12);
+        assert_eq!(vec![214; 6], periods[6..12]);
+        // never triggered when the delay is at least the speed
+        let periods = rendered_periods(build(6), 13);
+        assert_eq!(vec![428; 6], periods[6..12]);
+
+        // the sample volume is held back as well
+        let mut pt_mod = rows_mod(&[(1, 428, 0xc, 1, 0), (1, 214, 0xe, 0xd, 3)]);
+        pt_mod.samples[0].volume = 32;
+        assert_eq!(
+            vec![16, 16, 16, 32, 32, 32],
+            rendered_volumes(pt_mod, 12)[6..12]
+        );
+    }
+
+    #[test]
+    fn test_retrigger() {
+        let offsets = |rows: &[(u8, u16, u8, u8, u8)]| {
+            let mut pt_mod = rows_mod(rows);
+            pt_mod.samples[0] = ramp_sample();
+            // roughly 166 bytes per tick at period 428
+            render_ticks(pt_mod, rows.len() * 6, |cs| cs.offset as u16 / 100)
+        };
+        assert_eq!(vec![1, 3, 1, 3, 1, 3], offsets(&[(1, 428, 0xe, 9, 2)]));
+        assert_eq!(vec![1, 3, 4, 6, 8, 9], offsets(&[(1, 428, 0xe, 9, 0)]));
+        // E90 without a note leaves the sample playing
+        assert_eq!(
+            vec![11, 13, 14, 16, 18, 19],
+            offsets(&[(1, 428, 0, 0, 0), (0, 0, 0xe, 9, 0)])[6..12]
+        );
+    }
+
+    #[test]
+    fn test_fine_slides() {
+        let first_tick = |period, volume, t, x, y| {
+            let mut pt_mod = rows_mod(&[(1, period, t, x, y)]);
+            pt_mod.samples[0].volume = volume;
+            let player = player_after(pt_mod, 1);
+            let cs = &player.state.channels[0];
+            (cs.output_period, cs.output_volume)
+        };
+        assert_eq!((424, 32), first_tick(428, 32, 0xe, 1, 4));
+        assert_eq!((113, 32), first_tick(120, 32, 0xe, 1, 0xf));
+        assert_eq!((432, 32), first_tick(428, 32, 0xe, 2, 4));
+        assert_eq!((856, 32), first_tick(856, 32, 0xe, 2, 0xf));
+        assert_eq!((428, 36), first_tick(428, 32, 0xe, 0xa, 4));
+        assert_eq!((428, 64), first_tick(428, 60, 0xe, 0xa, 0xf));
+        assert_eq!((428, 17), first_tick(428, 32, 0xe, 0xb, 0xf));
+        assert_eq!((428, 0), first_tick(428, 8, 0xe, 0xb, 0xf));
+
+        // only once per division
+        let pt_mod = rows_mod(&[(1, 428, 0xe, 1, 4)]);
+        assert_eq!(vec![424; 6], rendered_periods(pt_mod, 6));
+
+        // normal slides stop at the same limits
+        let periods = rendered_periods(rows_mod(&[(1, 120, 0x1, 0, 4)]), 6);
+        assert_eq!(vec![120, 116, 113, 113, 113, 113], periods);
+        let periods = rendered_periods(rows_mod(&[(1, 850, 0x2, 0, 4)]), 6);
+        assert_eq!(vec![850, 854, 856, 856, 856, 856], periods);
+    }
+
+    #[test]
+    fn test_glissando() {
+        let rows = [(1, 428, 0xe, 3, 1), (1, 214, 0x3, 1, 0)];
+        let periods = rendered_periods(rows_mod(&rows), 12);
+        assert_eq!(vec![428, 404, 381, 360, 360, 339], periods[6..12]);
+    }
+
+    #[test]
+    fn test_set_finetune() {
+        let rows = [(1, 428, 0xe, 5, 0xf), (1, 428, 0, 0, 0), (1, 428, 0, 1, 0)];
+        let periods = rendered_periods(rows_mod(&rows), 14);
+        // finetune -1 for this note only, then the sample's again
+        assert_eq!(vec![431; 6], periods[0..6]);
+        assert_eq!(vec![428; 6], periods[6..12]);
+        assert_eq!(vec![428, 404], periods[12..14]);
+    }
+
+    #[test]
+    fn test_invert_loop() {
+        let player = player_after(rows_mod(&[(1, 428, 0xe, 0xf, 0xf)]), 6);
+        // speed F inverts one byte per tick, starting after the loop start
+        let data = player.funk_data[0].as_ref().unwrap();
+        assert_eq!(64, data[0]);
+        assert_eq!(vec![-65; 6], data[1..7]);
+        assert_eq!(64, data[7]);
+
+        // the module is untouched
+        let pt_mod = player.into_mod();
+        assert_eq!(vec![64; 32], pt_mod.samples[0].data[0..32]);
+    }
+
+    #[test]
+    fn test_rows_without_sample() {
+        // a note without a sample reuses the last one and keeps the volume
+        let rows = [(1, 428, 0xc, 1, 0), (0, 214, 0, 0, 0)];
+        let player = player_after(rows_mod(&rows), 7);
+        let cs = &player.state.channels[0];
+        assert_eq!((Some(1), 214, 16), (cs.sample_no, cs.period, cs.volume));
+        assert!(cs.offset < 32.0);
+
+        // a sample without a note resets the volume, the note keeps playing
+        let rows = [(1, 428, 0xc, 1, 0), (1, 0, 0, 0, 0)];
+        assert_eq!(vec![16, 64], rendered_volumes(rows_mod(&rows), 7)[5..7]);
+        assert_eq!(vec![428; 12], rendered_periods(rows_mod(&rows), 12));
+
+        // effects without a note or sample apply to the playing note
+        let rows = [(1, 428, 0xc, 1, 0), (0, 0, 0xa, 2, 0), (0, 0, 0xf, 0, 3)];
+        let volumes = rendered_volumes(rows_mod(&rows), 15);
+        assert_eq!(vec![16, 18, 20, 22, 24, 26], volumes[6..12]);
+        assert_eq!(vec![26, 26, 26], volumes[12..15]);
+        let player = player_after(rows_mod(&rows), 13);
+        assert_eq!(3, player.state.ticks_per_div);
+
+        // E9x without a note restarts on the first tick as well
+        let mut pt_mod = rows_mod(&[(1, 428, 0, 0, 0), (0, 0, 0xe, 9, 3)]);
+        pt_mod.samples[0] = ramp_sample();
+        let offsets = render_ticks(pt_mod, 12, |cs| cs.offset as u16 / 100);
+        assert_eq!(vec![1, 3, 4, 6, 8, 9], offsets[0..6]);
+        assert_eq!(vec![1, 3, 4, 1, 3, 4], offsets[6..12]);
+    }
+
+    #[test]
+    fn test_effect_memory() {
+        let rows = [(1, 428, 0x1, 0, 2), (0, 0, 0x1, 0, 0), (0, 0, 0, 0, 0)];
+        let periods = rendered_periods(rows_mod(&rows), 18);
+        assert_eq!(vec![418, 416, 414, 412, 410, 408], periods[6..12]);
+        assert_eq!(vec![408; 6], periods[12..18]);
+
+        let rows = [(1, 428, 0x2, 0, 3), (0, 0, 0x2, 0, 0)];
+        let periods = rendered_periods(rows_mod(&rows), 12);
+        assert_eq!(vec![443, 446, 449, 452, 455, 458], periods[6..12]);
+
+        let rows = [(1, 428, 0, 0, 0), (0, 214, 0x3, 2, 0), (0, 0, 0x3, 0, 0)];
+        let periods = rendered_periods(rows_mod(&rows), 18);
+        assert_eq!(vec![428, 396, 364, 332, 300, 268], periods[6..12]);
+        assert_eq!(vec![268, 236, 214, 214, 214, 214], periods[12..18]);
+
+        // 400 continues the vibrato with the last speed and depth
+        let rows = [(1, 428, 0x4, 8, 15), (0, 0, 0x4, 0, 0)];
+        let periods = rendered_periods(rows_mod(&rows), 12);
+        assert_eq!(vec![428, 407, 399, 407, 428, 449], periods[6..12]);
+
+        // 900 on a note without a sample: the start was moved twice by 902
+        let mut pt_mod = rows_mod(&[(1, 428, 0x9, 0, 2), (0, 428, 0, 0, 0)]);
+        pt_mod.samples[0] = ramp_sample();
+        let offsets = render_ticks(pt_mod, 7, |cs| cs.offset as u16 / 100);
+        assert_eq!((6, 11), (offsets[0], offsets[6]));
+    }
+
+    // first stereo tick of a 4 channel module with a note in channel 0
+    fn stereo_tick(options: PlayerOptions, t: u8, x: u8, y: u8) -> (Vec<i16>, Vec<i16>) {
+        let pt_mod = rows_mod(&[(1, 428, t, x, y)]);
+        let mut player =
+            ProtrackerPlayer::with_options(pt_mod, ClockFreq::Pal, output_format(8000), options)
+                .unwrap();
+        match player.get_samples() {
+            Ok(SampleOutput::I16(buf)) => (
+                buf.iter().step_by(2).copied().collect(),
+                buf.iter().skip(1).step_by(2).copied().collect(),
+            ),
+            _ => panic!("no output"),
+        }
+    }
+
+    #[test]
+    fn test_stereo_separation() {
+        let separation = |stereo_separation| PlayerOptions {
+            stereo_separation,
+            ..PlayerOptions::default()
+        };
+
+        let (left, right) = stereo_tick(separation(100), 0, 0, 0);
+        assert!(left.iter().any(|v| *v != 0));
+        assert!(right.iter().all(|v| *v == 0));
+
+        let (left, right) = stereo_tick(separation(0), 0, 0, 0);
+        assert_eq!(left, right);
+
+        // 50%: the channel is at 1/4, so the right side gets a third of the left
+        let (left, right) = stereo_tick(separation(50), 0, 0, 0);
+        for (l, r) in left.iter().zip(right.iter()) {
+            assert!((*l as i32 - 3 * *r as i32).abs() <= 3);
+        }
+    }
+
+    #[test]
+    fn test_pan_effects() {
+        let pan_effects = PlayerOptions {
+            pan_effects: true,
+            ..PlayerOptions::default()
+        };
+
+        // ignored unless enabled, as in ProTracker
+        let (_, right) = stereo_tick(PlayerOptions::default(), 0x8, 0xf, 0xf);
+        assert!(right.iter().all(|v| *v == 0));
+
+        let (left, right) = stereo_tick(pan_effects.clone(), 0x8, 0xf, 0xf);
+        assert!(left.iter().all(|v| *v == 0));
+        assert!(right.iter().any(|v| *v != 0));
+
+        // 0x80 is just right of centre
+        let (left, right) = stereo_tick(pan_effects.clone(), 0x8, 0x8, 0);
+        for (l, r) in left.iter().zip(right.iter()) {
+            assert!((*l as i32 - *r as i32).abs() <= (*l as i32).abs() / 100 + 1);
+        }
+
+        let (left, right) = stereo_tick(pan_effects, 0xe, 0x8, 0xf);
+        assert!(left.iter().all(|v| *v == 0));
+        assert!(right.iter().any(|v| *v != 0));
+    }
+
+    // mono output of a looped sine with `cycle` points per period, played at
+    // `period` in a single channel, without the first tick
+    fn render_sine(
+        interpolation: Interpolation,
+        cycle: usize,
+        period: u16,
+        sample_rate: u32,
+    ) -> Vec<f64> {
+        let mut pt_mod = test_mod(1, 1);
+        let sample = &mut pt_mod.samples[0];
+        sample.data = (0..64)
+            .map(|i| (127.0 * (2.0 * PI * i as f64 / cycle as f64).sin()).round() as i8)
+            .collect();
+        pt_mod.patterns[0].divisions[0].channel_data[0] = cell(1, period, 0, 0, 0);
+        let options = PlayerOptions {
+            interpolation,
+            ..PlayerOptions::default()
+        };
+        let output_format = OutputFormat {
+            sample_rate,
+            sample_format: SampleFormat::F32,
+            channel_count: 1,
+        };
+        render(pt_mod, options, output_format, 12)
+    }
+
+    // output of `num_ticks` ticks after the first, which is skipped
+    fn render(
+        pt_mod: ProtrackerMod,
+        options: PlayerOptions,
+        output_format: OutputFormat,
+        num_ticks: usize,
+    ) -> Vec<f64> {
+        let mut player =
+            ProtrackerPlayer::with_options(pt_mod, ClockFreq::Pal, output_format, options).unwrap();
+        player.get_samples().unwrap();
+        let mut values = vec![];
+        for _ in 0..num_ticks {
+            match player.get_samples().unwrap() {
+                SampleOutput::I16(buf) => values.extend(buf.iter().map(|v| *v as f64)),
+                SampleOutput::U16(buf) => values.extend(buf.iter().map(|v| *v as f64)),
+                SampleOutput::F32(buf) => values.extend(buf.iter().map(|v| *v as f64)),
+            }
+        }
+        values
+    }
+
+    fn energy(values: &[f64]) -> f64 {
+        values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64
+    }
+
+    // energy left after removing the best fitting sine at `freq` cycles per
+    // output sample
+    fn residual_energy(values: &[f64], freq: f64) -> f64 {
+        let w = 2.0 * PI * freq;
+        let (mut ss, mut cc, mut sc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (n, y) in values.iter().enumerate() {
+            let (s, c) = (w * n as f64).sin_cos();
+            ss += s * s;
+            cc += c * c;
+            sc += s * c;
+            ys += y * s;
+            yc += y * c;
+        }
+        let det = ss * cc - sc * sc;
+        let a = (ys * cc - yc * sc) / det;
+        let b = (yc * ss - ys * sc) / det;
+        let residual: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(n, y)| {
+                let (s, c) = (w * n as f64).sin_cos();
+                y - a * s - b * c
+            })
+            .collect();
+        energy(&residual)
+    }
+
+    const INTERPOLATIONS: [Interpolation; 4] = [
+        Interpolation::Nearest,
+        Interpolation::Linear,
+        Interpolation::Cubic,
+        Interpolation::Sinc,
+    ];
+
+    #[test]
+    fn test_interpolation_images() {
+        // 8 point sine played below the output rate: everything besides the
+        // sine itself is aliasing from the resampling
+        let advance = CLOCK_FREQ_PAL / 428.0 / (2.0 * 44100.0);
+        let errors: Vec<_> = INTERPOLATIONS
+            .iter()
+            .map(|interpolation| {
+                let values = render_sine(*interpolation, 8, 428, 44100);
+                residual_energy(&values, advance / 8.0) / energy(&values)
+            })
+            .collect();
+        for pair in errors.windows(2) {
+            assert!(pair[1] < pair[0] / 2.0, "{:?}", errors);
+        }
+        assert!(errors[3] < 1e-4, "{:?}", errors);
+    }
+
+    #[test]
+    fn test_interpolation_downsampling() {
+        // a sine at half the output's Nyquist frequency when played at period
+        // 113 comes out above it at 8 kHz, so it can only be heard as alias
+        let energies: Vec<_> = INTERPOLATIONS
+            .iter()
+            .map(|interpolation| energy(&render_sine(*interpolation, 4, 113, 8000)))
+            .collect();
+        for energy in &energies[..3] {
+            assert!(energies[3] < energy / 1000.0, "{:?}", energies);
+        }
+    }
+
+    #[test]
+    fn test_led_filter() {
+        // square wave at ~3.9 kHz, with E0x on the second division
+        let energy_after = |filter, x| {
+            let mut pt_mod = rows_mod(&[(1, 113, 0, 0, 0), (0, 0, 0xe, 0, x)]);
+            let sample = &mut pt_mod.samples[0];
+            sample.data = vec![64, 64, 64, 64, -64, -64, -64, -64];
+            sample.length = 8;
+            sample.repeat_length = 8;
+            let options = PlayerOptions {
+                filter,
+                ..PlayerOptions::default()
+            };
+            let output_format = OutputFormat {
+                sample_rate: 44100,
+                sample_format: SampleFormat::F32,
+                channel_count: 1,
+            };
+            // from the second tick of the second division, 882 samples per tick
+            let values = render(pt_mod, options, output_format, 11);
+            energy(&values[6 * 882..])
+        };
+
+        let unfiltered = energy_after(AmigaFilter::Off, 0);
+        let a500 = energy_after(AmigaFilter::A500, 1);
+        let a500_led = energy_after(AmigaFilter::A500, 0);
+        let a1200 = energy_after(AmigaFilter::A1200, 1);
+        let a1200_led = energy_after(AmigaFilter::A1200, 0);
+        assert!(a500 < unfiltered * 0.8);
+        assert!(a500_led < a500 * 0.5);
+        assert!(a1200 > unfiltered * 0.9);
+        assert!(a1200_led < a1200 * 0.5);
+    }
+
+    // magnitude at `freq` cycles per sample, Hann windowed
+    fn magnitude(values: &[f64], freq: f64) -> f64 {
+        let len = values.len() as f64;
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, y) in values.iter().enumerate() {
+            let window = 0.5 - 0.5 * (2.0 * PI * n as f64 / len).cos();
+            let (s, c) = (2.0 * PI * freq * n as f64).sin_cos();
+            re += y * window * c;
+            im += y * window * s;
+        }
+        (re * re + im * im).sqrt() / len
+    }
+
+    #[test]
+    fn test_paula_mixer() {
+        // 4 byte square at period 113: the fundamental is at ~7.8 kHz, the
+        // 5th harmonic at ~39.2 kHz aliases to ~4.9 kHz at 44.1 kHz
+        let render_square = |mixer| {
+            let mut pt_mod = test_mod(1, 1);
+            let sample = &mut pt_mod.samples[0];
+            sample.data = vec![64, 64, -64, -64];
+            sample.length = 4;
+            sample.repeat_length = 4;
+            pt_mod.patterns[0].divisions[0].channel_data[0] = cell(1, 113, 0, 0, 0);
+            let options = PlayerOptions {
+                mixer,
+                ..PlayerOptions::default()
+            };
+            let output_format = OutputFormat {
+                sample_rate: 44100,
+                sample_format: SampleFormat::F32,
+                channel_count: 1,
+            };
+            render(pt_mod, options, output_format, 10)
+        };
+        let byte_rate = CLOCK_FREQ_PAL / 2.0 / 113.0;
+        let fundamental = byte_rate / 4.0 / 44100.0;
+        let alias = 1.0 - 5.0 * fundamental;
+
+        let resampled = render_square(Mixer::Resampler);
+        let paula = render_square(Mixer::Paula { dma_limit: false });
+        let ratio = magnitude(&paula, fundamental) / magnitude(&resampled, fundamental);
+        assert!(ratio > 0.8 && ratio < 1.25, "{}", ratio);
+        assert!(magnitude(&resampled, alias) > magnitude(&resampled, fundamental) / 20.0);
+        assert!(magnitude(&paula, alias) < magnitude(&paula, fundamental) / 1000.0);
+
+        // at the DMA limit the square is played at period 124
+        let limited = render_square(Mixer::Paula { dma_limit: true });
+        let fundamental_124 = CLOCK_FREQ_PAL / 2.0 / 124.0 / 4.0 / 44100.0;
+        assert!(magnitude(&limited, fundamental_124) > magnitude(&limited, fundamental) * 10.0);
+
+        // the delayed output follows the last tick, fading to silence
+        let mut pt_mod = test_mod(1, 1);
+        pt_mod.samples[0].data = vec![64; 64];
+        pt_mod.patterns[0].divisions[0].channel_data[0] = cell(1, 428, 0, 0, 0);
+        let options = PlayerOptions {
+            mixer: Mixer::Paula { dma_limit: false },
+            ..PlayerOptions::default()
+        };
+        let output_format = OutputFormat {
+            sample_rate: 44100,
+            sample_format: SampleFormat::F32,
+            channel_count: 1,
+        };
+        let values = render(pt_mod, options, output_format, 64 * 6);
+        let tail = &values[(64 * 6 - 1) * 882..];
+        assert_eq!(33, tail.len());
+        assert!((tail[0] - 0.5).abs() < 1e-3);
+        assert!((tail[15] - 0.25).abs() < 1e-6);
+        assert!(tail[32].abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_song_length() {
+        // 32 divisions at 125 BPM, then 32 at 151 BPM at speed 6, then a
+        // pattern at 151 BPM and speed 3
+        let tick_counts = [(125, 32 * 6), (151, 32 * 6 + 64 * 3)];
+        let rendered_length = |cia_timing| {
+            let mut pt_mod = test_mod(4, 2);
+            pt_mod.patterns[0].divisions[32].channel_data[1] = cell(0, 0, 0xf, 9, 7);
+            pt_mod.patterns[1].divisions[0].channel_data[1] = cell(0, 0, 0xf, 0, 3);
+            let options = PlayerOptions {
+                cia_timing,
+                ..PlayerOptions::default()
+            };
+            let mut player = ProtrackerPlayer::with_options(
+                pt_mod,
+                ClockFreq::Pal,
+                output_format(48000),
+                options,
+            )
+            .unwrap();
+            let mut length = 0;
+            while let Ok(SampleOutput::I16(buf)) = player.get_samples() {
+                if buf.is_empty() {
+                    break;
+                }
+                length += buf.len() / 2;
+            }
+            length as f64
+        };
+
+        let seconds: f64 = tick_counts
+            .iter()
+            .map(|(bpm, ticks)| *ticks as f64 * 2.5 / *bpm as f64)
+            .sum();
+        assert!((rendered_length(false) - seconds * 48000.0).abs() <= 1.0);
+
+        let cia_seconds: f64 = tick_counts
+            .iter()
+            .map(|(bpm, ticks)| *ticks as f64 * (1_773_447 / bpm) as f64 / 709_378.92)
+            .sum();
+        assert!((rendered_length(true) - cia_seconds * 48000.0).abs() <= 1.0);
+        assert!((cia_seconds - seconds).abs() > 0.0001);
+    }
+
+    // ticks 2..4 of a 1 channel module playing a full scale square
+    fn render_full_scale(
+        sample_format: SampleFormat,
+        channel_count: u16,
+        options: PlayerOptions,
+    ) -> Vec<f64> {
+        let mut pt_mod = test_mod(1, 1);
+        pt_mod.samples[0].data = [vec![127; 32], vec![-128; 32]].concat();
+        pt_mod.patterns[0].divisions[0].channel_data[0] = cell(1, 428, 0, 0, 0);
+        let output_format = OutputFormat {
+            sample_rate: 8000,
+            sample_format,
+            channel_count,
+        };
+        render(pt_mod, options, output_format, 3)
+    }
+
+    fn assert_square(values: &[f64], high: f64, low: f64) {
+        assert!(values.iter().all(|v| *v == high || *v == low));
+        assert!(values.contains(&high) && values.contains(&low));
+    }
+
+    #[test]
+    fn test_full_scale() {
+        let options = PlayerOptions::default;
+        // the full scale of the sample is the full scale of the output
+        assert_square(
+            &render_full_scale(SampleFormat::I16, 1, options()),
+            32512.0,
+            -32768.0,
+        );
+        assert_square(
+            &render_full_scale(SampleFormat::U16, 1, options()),
+            65280.0,
+            0.0,
+        );
+        assert_square(
+            &render_full_scale(SampleFormat::F32, 1, options()),
+            127.0 / 128.0,
+            -1.0,
+        );
+
+        // stereo: the channel is on the left
+        let values = render_full_scale(SampleFormat::I16, 2, options());
+        let right: Vec<_> = values.iter().skip(1).step_by(2).copied().collect();
+        assert!(right.iter().all(|v| *v == 0.0));
+        let left: Vec<_> = values.iter().step_by(2).copied().collect();
+        assert_square(&left, 32512.0, -32768.0);
+        let values = render_full_scale(SampleFormat::U16, 2, options());
+        assert!(values.iter().skip(1).step_by(2).all(|v| *v == 32768.0));
+        let left: Vec<_> = values.iter().step_by(2).copied().collect();
+        assert_square(&left, 65280.0, 0.0);
+        let values = render_full_scale(SampleFormat::F32, 2, options());
+        assert!(values.iter().skip(1).step_by(2).all(|v| *v == 0.0));
+        let left: Vec<_> = values.iter().step_by(2).copied().collect();
+        assert_square(&left, 127.0 / 128.0, -1.0);
+
+        // half the master gain is half the level
+        let half = PlayerOptions {
+            master_gain: 0.5,
+            ..PlayerOptions::default()
+        };
+        assert_square(
+            &render_full_scale(SampleFormat::I16, 1, half),
+            16256.0,
+            -16384.0,
+        );
+    }
+
+    #[test]
+    fn test_clipping() {
+        let clipping = |clipping| PlayerOptions {
+            master_gain: 2.0,
+            clipping,
+            ..PlayerOptions::default()
+        };
+
+        // saturated in every format
+        assert_square(
+            &render_full_scale(SampleFormat::I16, 1, clipping(Clipping::Hard)),
+            32767.0,
+            -32768.0,
+        );
+        assert_square(
+            &render_full_scale(SampleFormat::U16, 1, clipping(Clipping::Hard)),
+            65535.0,
+            0.0,
+        );
+        assert_square(
+            &render_full_scale(SampleFormat::F32, 1, clipping(Clipping::Hard)),
+            1.0,
+            -1.0,
+        );
+
+        let values = render_full_scale(SampleFormat::F32, 1, clipping(Clipping::Soft));
+        let high = values.iter().copied().fold(f64::MIN, f64::max);
+        let low = values.iter().copied().fold(f64::MAX, f64::min);
+        assert!(high > 0.9 && high < 1.0);
+        assert!(low < -0.9 && low > -1.0);
+
+        // the limiter halves the gain for the negative peaks and releases
+        // towards 1 / 1.98 on the positive half wave, without clipping
+        let values = render_full_scale(SampleFormat::I16, 1, clipping(Clipping::Limiter));
+        assert!(values.contains(&-32768.0) && values.contains(&32512.0));
+        assert!(values
+            .iter()
+            .all(|v| *v == -32768.0 || (32512.0..32767.0).contains(v)));
+
+        // the frames held back by the lookahead follow the last tick
+        let mut pt_mod = test_mod(1, 1);
+        pt_mod.samples[0].data = vec![-128; 64];
+        pt_mod.patterns[0].divisions[0].channel_data[0] = cell(1, 428, 0, 0, 0);
+        let values = render(
+            pt_mod,
+            clipping(Clipping::Limiter),
+            output_format(8000),
+            64 * 6,
+        );
+        let tail = &values[(64 * 6 - 1) * 160 * 2..];
+        assert_eq!(16 * 2, tail.len());
+        assert!(tail.iter().step_by(2).all(|v| *v == -32768.0));
+    }
 }
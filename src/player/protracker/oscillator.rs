@@ -0,0 +1,139 @@
+// vibrato and tremolo oscillators, following ProTracker 2.3
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Waveform {
+    Sine,
+    RampDown,
+    Square,
+    Random,
+}
+
+pub struct Oscillator {
+    pub waveform: Waveform,
+    // reset the position on every new note
+    pub retrigger: bool,
+    pub speed: u8,
+    pub depth: u8,
+    // 0..255, one cycle is 64 steps of 4
+    pub pos: u8,
+}
+
+static SINE_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+impl Oscillator {
+    pub fn default() -> Oscillator {
+        Oscillator {
+            waveform: Waveform::Sine,
+            retrigger: true,
+            speed: 0,
+            depth: 0,
+            pos: 0,
+        }
+    }
+
+    // a zero parameter keeps the previous value
+    pub fn set_params(&mut self, speed: u8, depth: u8) {
+        if speed > 0 {
+            self.speed = speed;
+        }
+        if depth > 0 {
+            self.depth = depth;
+        }
+    }
+
+    // E4x / E7x parameter: bits 0-1 select the waveform, bit 2 disables retrigger
+    pub fn set_waveform(&mut self, param: u8) {
+        self.waveform = match param & 0x03 {
+            0 => Waveform::Sine,
+            1 => Waveform::RampDown,
+            2 => Waveform::Square,
+            _ => Waveform::Random,
+        };
+        self.retrigger = param & 0x04 == 0;
+    }
+
+    pub fn note_on(&mut self) {
+        if self.retrigger {
+            self.pos = 0;
+        }
+    }
+
+    /// Signed offset at the current position, `(amplitude * depth) >> shift`.
+    ///
+    /// The ramp direction is taken from `ramp_pos`, which is the oscillator's
+    /// own position for vibrato. PT's tremolo reads the vibrato position
+    /// here, so callers pass that in to reproduce it. `random` is the
+    /// amplitude used by the random waveform.
+    pub fn delta(&self, shift: u32, ramp_pos: u8, random: u8) -> i32 {
+        let idx = (self.pos >> 2) & 0x1f;
+        let amplitude = match self.waveform {
+            Waveform::Sine => SINE_TABLE[idx as usize],
+            Waveform::RampDown if (ramp_pos as i8) < 0 => 255 - (idx << 3),
+            Waveform::RampDown => idx << 3,
+            Waveform::Square => 255,
+            Waveform::Random => random,
+        };
+        let delta = (amplitude as i32 * self.depth as i32) >> shift;
+        if (self.pos as i8) < 0 {
+            -delta
+        } else {
+            delta
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.pos = self.pos.wrapping_add(self.speed << 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine() {
+        let mut osc = Oscillator::default();
+        osc.set_params(8, 15);
+        let deltas: Vec<_> = (0..8)
+            .map(|_| {
+                let delta = osc.delta(7, osc.pos, 0);
+                osc.advance();
+                delta
+            })
+            .collect();
+        assert_eq!(vec![0, 21, 29, 21, 0, -21, -29, -21], deltas);
+
+        // zero parameters keep the previous ones
+        osc.set_params(0, 4);
+        assert_eq!((8, 4), (osc.speed, osc.depth));
+    }
+
+    #[test]
+    fn test_waveforms() {
+        let mut osc = Oscillator::default();
+        osc.set_params(1, 8);
+        osc.set_waveform(1);
+        osc.pos = 4;
+        assert_eq!(1, osc.delta(6, osc.pos, 0));
+        // negative half uses the reversed ramp and the opposite sign
+        osc.pos = 132;
+        assert_eq!(-30, osc.delta(6, osc.pos, 0));
+        assert_eq!(-1, osc.delta(6, 4, 0));
+
+        osc.set_waveform(2);
+        assert_eq!(-31, osc.delta(6, 0, 0));
+        osc.set_waveform(7);
+        assert_eq!(Waveform::Random, osc.waveform);
+        assert_eq!(-25, osc.delta(6, 0, 200));
+
+        assert!(!osc.retrigger);
+        osc.note_on();
+        assert_eq!(132, osc.pos);
+        osc.set_waveform(0);
+        osc.note_on();
+        assert_eq!(0, osc.pos);
+    }
+}
@@ -0,0 +1,177 @@
+// output stage: clipping or limiting of the mix, and conversion to the
+// sample format
+
+use std::collections::VecDeque;
+
+/// Treatment of a mix which exceeds full scale.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Clipping {
+    /// Saturate at full scale
+    #[default]
+    Hard,
+    /// Compress smoothly above 3/4 of full scale, never reaching it
+    Soft,
+    /// Lookahead limiter with 2 ms attack and 50 ms release, which lowers
+    /// the gain just enough to stay within full scale. The output is
+    /// delayed by the lookahead, which follows after the song end.
+    Limiter,
+}
+
+// start of the soft clipping curve
+const SOFT_KNEE: f64 = 0.75;
+
+pub fn soft_clip(val: f64) -> f64 {
+    if val.abs() <= SOFT_KNEE {
+        val
+    } else {
+        let over = (val.abs() - SOFT_KNEE) / (1.0 - SOFT_KNEE);
+        val.signum() * (SOFT_KNEE + (1.0 - SOFT_KNEE) * over.tanh())
+    }
+}
+
+pub struct Limiter {
+    lookahead: usize,
+    release: f64,
+    // frames not yet output, with the gain each needs to stay in full scale
+    delay: VecDeque<([f64; 2], f64)>,
+    // lowest gain needed within the lookahead, released slowly, for the
+    // last `lookahead` frames; the average ramps the gain down in time
+    held: VecDeque<f64>,
+    gain: f64,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32) -> Limiter {
+        let lookahead = (sample_rate as usize / 500).max(1);
+        Limiter {
+            lookahead,
+            release: 1.0 - (-1.0 / (0.05 * sample_rate as f64)).exp(),
+            delay: VecDeque::with_capacity(lookahead + 1),
+            held: VecDeque::with_capacity(lookahead + 1),
+            gain: 1.0,
+        }
+    }
+
+    /// Frames by which the output is delayed.
+    pub fn lookahead(&self) -> usize {
+        self.lookahead
+    }
+
+    /// Replace `frame` (1 or 2 channels) by the limited frame from
+    /// `lookahead` frames before. The channels share the gain.
+    pub fn process(&mut self, frame: &mut [f64]) {
+        let peak = frame.iter().fold(0.0, |peak: f64, val| peak.max(val.abs()));
+        let required = if peak > 1.0 { 1.0 / peak } else { 1.0 };
+        let mut delayed = [0.0; 2];
+        delayed[..frame.len()].copy_from_slice(frame);
+        self.delay.push_back((delayed, required));
+        if self.delay.len() <= self.lookahead {
+            frame.iter_mut().for_each(|val| *val = 0.0);
+            return;
+        }
+
+        let lowest = self.delay.iter().fold(1.0, |gain: f64, e| gain.min(e.1));
+        self.gain = lowest.min(self.gain + (1.0 - self.gain) * self.release);
+        self.held.push_back(self.gain);
+        if self.held.len() > self.lookahead {
+            self.held.pop_front();
+        }
+        // every held gain covers the frame going out, so the average does too
+        let gain = self.held.iter().sum::<f64>() / self.held.len() as f64;
+
+        if let Some((delayed, _)) = self.delay.pop_front() {
+            for (val, delayed) in frame.iter_mut().zip(delayed.iter()) {
+                *val = delayed * gain;
+            }
+        }
+    }
+}
+
+// full scale is 1.0, sample values -128..127 at volume 64 map to the whole
+// range of the integer formats
+
+pub fn to_i16(val: f64) -> i16 {
+    (val * 32768.0).floor().clamp(-32768.0, 32767.0) as i16
+}
+
+pub fn to_u16(val: f64) -> u16 {
+    (val * 32768.0 + 32768.0).floor().clamp(0.0, 65535.0) as u16
+}
+
+pub fn to_f32(val: f64) -> f32 {
+    val.clamp(-1.0, 1.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        assert_eq!(
+            vec![-32768, -32768, -16384, 0, 32512, 32767, 32767],
+            [-2.0, -1.0, -0.5, 0.0, 127.0 / 128.0, 1.0, 2.0]
+                .iter()
+                .map(|val| to_i16(*val))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 0, 16384, 32768, 65280, 65535, 65535],
+            [-2.0, -1.0, -0.5, 0.0, 127.0 / 128.0, 1.0, 2.0]
+                .iter()
+                .map(|val| to_u16(*val))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(-1.0, to_f32(-3.0));
+        assert_eq!(0.25, to_f32(0.25));
+        assert_eq!(1.0, to_f32(1.5));
+    }
+
+    #[test]
+    fn test_soft_clip() {
+        assert_eq!(0.5, soft_clip(0.5));
+        assert_eq!(-0.75, soft_clip(-0.75));
+        let mut last = 0.75;
+        for i in 1..40 {
+            let val = soft_clip(0.75 + i as f64 * 0.05);
+            assert!(val > last && val < 1.0);
+            last = val;
+        }
+        assert_eq!(-soft_clip(1.2), soft_clip(-1.2));
+    }
+
+    #[test]
+    fn test_limiter() {
+        let mut limiter = Limiter::new(8000);
+        assert_eq!(16, limiter.lookahead);
+
+        // a burst at 4x full scale in the middle of a quiet signal
+        let input: Vec<_> = (0..4000)
+            .map(|n| {
+                let amplitude = if (500..600).contains(&n) { 4.0 } else { 0.5 };
+                if n % 2 == 0 {
+                    amplitude
+                } else {
+                    -amplitude
+                }
+            })
+            .collect();
+        let output: Vec<_> = input
+            .iter()
+            .map(|val| {
+                let mut frame = [*val, -*val];
+                limiter.process(&mut frame);
+                assert_eq!(frame[0], -frame[1]);
+                frame[0]
+            })
+            .collect();
+
+        assert!(output.iter().all(|val| val.abs() <= 1.0 + 1e-12));
+        // delayed by the lookahead, untouched before the burst
+        assert_eq!(&input[..480], &output[16..496]);
+        // the burst is brought down to full scale
+        assert!((output[550 + 16].abs() - 1.0).abs() < 1e-9);
+        // and the gain recovers afterwards
+        assert!((output[3999].abs() - 0.5).abs() < 0.001);
+    }
+}
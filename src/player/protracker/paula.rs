@@ -0,0 +1,271 @@
+// Paula emulation: the DMA fetches a byte every `period` clocks, the DAC
+// holds it until the next one, and each change of the output level is
+// synthesized as a band-limited step at the output rate
+
+use super::ChannelState;
+use crate::format::protracker::Sample;
+use std::f64::consts::PI;
+
+/// Mixer used to render the channels.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Mixer {
+    /// Resample each channel with the selected `Interpolation`
+    #[default]
+    Resampler,
+    /// Emulate Paula's DMA and DAC with band-limited steps; the output is
+    /// delayed by 16 samples, which follow after the song end. Periods are
+    /// limited to 113, the lowest PT writes to the hardware, or with
+    /// `dma_limit` to 124, the lowest at which the DMA keeps up on a real
+    /// Amiga.
+    Paula { dma_limit: bool },
+}
+
+const DMA_MIN_PERIOD: u16 = 124;
+
+// zero crossings of the step's sinc on each side, which is also the delay
+// of the output in samples
+const STEP_HALF_WIDTH: usize = 16;
+// table points per output sample
+const STEP_PHASES: usize = 256;
+// cutoff relative to the output's Nyquist frequency
+const STEP_CUTOFF: f64 = 0.9;
+
+pub struct Paula {
+    // Paula clocks per output sample
+    clocks: f64,
+    min_period: u16,
+    // step from 0 to 1, band-limited to the output rate
+    step: Vec<f64>,
+    voices: Vec<Voice>,
+}
+
+struct Voice {
+    // last byte fetched, held by the DAC
+    data: i8,
+    // DAC output, data times volume
+    level: f64,
+    // Paula clocks until the next fetch
+    countdown: f64,
+    // changes of the output for the next samples, from recent steps
+    changes: Vec<f64>,
+    pos: usize,
+    output: f64,
+}
+
+impl Paula {
+    /// Paula runs at half the CPU clock `clock_freq`.
+    pub fn new(num_channels: usize, clock_freq: f64, sample_rate: u32, mixer: Mixer) -> Paula {
+        let min_period = match mixer {
+            Mixer::Paula { dma_limit: true } => DMA_MIN_PERIOD,
+            _ => super::PERIOD_MIN as u16,
+        };
+        Paula {
+            clocks: clock_freq / 2.0 / sample_rate as f64,
+            min_period,
+            step: step_table(),
+            voices: (0..num_channels).map(|_| Voice::default()).collect(),
+        }
+    }
+
+    /// Output samples until a step started now has settled, which covers
+    /// the delayed output.
+    pub fn tail_len(&self) -> usize {
+        2 * STEP_HALF_WIDTH + 1
+    }
+
+    /// Next output value of a channel playing `sample` (with its possibly
+    /// modified data), in the same units as the resampler.
+    pub fn next_sample(
+        &mut self,
+        voice_no: usize,
+        channel: &mut ChannelState,
+        sample: Option<(&Sample, &[i8])>,
+    ) -> f64 {
+        let voice = &mut self.voices[voice_no];
+        let step = &self.step;
+        match sample {
+            Some((sample, data)) if channel.output_period > 0 => {
+                let period = channel.output_period.max(self.min_period) as f64;
+                let volume = channel.output_volume as f64;
+                // volume changes take effect at the start of the tick
+                voice.set_level(step, 0.0, voice.data as f64 * volume);
+                let mut elapsed = 0.0;
+                while elapsed + voice.countdown < self.clocks {
+                    elapsed += voice.countdown;
+                    voice.countdown = period;
+                    voice.data = fetch(channel, sample, data);
+                    voice.set_level(step, elapsed / self.clocks, voice.data as f64 * volume);
+                }
+                voice.countdown -= self.clocks - elapsed;
+            }
+            _ => voice.set_level(step, 0.0, 0.0),
+        }
+        voice.next()
+    }
+}
+
+impl Voice {
+    fn default() -> Voice {
+        Voice {
+            data: 0,
+            level: 0.0,
+            countdown: 0.0,
+            changes: vec![0.0; 2 * STEP_HALF_WIDTH + 1],
+            pos: 0,
+            output: 0.0,
+        }
+    }
+
+    // change the level at `frac` (0..1) of the time between the previous
+    // and the next output sample
+    fn set_level(&mut self, step: &[f64], frac: f64, level: f64) {
+        let delta = level - self.level;
+        if delta == 0.0 {
+            return;
+        }
+        self.level = level;
+        let len = self.changes.len();
+        for j in 0..len {
+            let x = (j + 1) as f64 - frac - STEP_HALF_WIDTH as f64;
+            let change = step_at(step, x) - step_at(step, x - 1.0);
+            self.changes[(self.pos + j) % len] += delta * change;
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        self.output += self.changes[self.pos];
+        self.changes[self.pos] = 0.0;
+        self.pos = (self.pos + 1) % self.changes.len();
+        self.output
+    }
+}
+
+// fetch the byte at the channel's offset and move on; at the end Paula
+// continues with the loop, which for one-shot samples is their first word
+fn fetch(channel: &mut ChannelState, sample: &Sample, data: &[i8]) -> i8 {
+    let offset = channel.offset as usize;
+    let value = data.get(offset).copied().unwrap_or(0);
+    let end = if channel.in_loop {
+        sample.repeat_offset + sample.repeat_length.max(2)
+    } else {
+        channel.sample_end
+    };
+    if offset + 1 >= end as usize {
+        channel.offset = sample.repeat_offset as f64;
+        channel.in_loop = true;
+    } else {
+        channel.offset = (offset + 1) as f64;
+    }
+    value
+}
+
+// integral of a Blackman windowed sinc over -STEP_HALF_WIDTH..STEP_HALF_WIDTH
+fn step_table() -> Vec<f64> {
+    let half_width = STEP_HALF_WIDTH as f64;
+    let len = 2 * STEP_HALF_WIDTH * STEP_PHASES + 1;
+    let impulse: Vec<_> = (0..len)
+        .map(|i| {
+            let x = i as f64 / STEP_PHASES as f64 - half_width;
+            let window =
+                0.42 + 0.5 * (PI * x / half_width).cos() + 0.08 * (2.0 * PI * x / half_width).cos();
+            let arg = PI * STEP_CUTOFF * x;
+            let sinc = if x == 0.0 { 1.0 } else { arg.sin() / arg };
+            window * sinc
+        })
+        .collect();
+    let mut step = vec![0.0; len];
+    for i in 1..len {
+        step[i] = step[i - 1] + (impulse[i - 1] + impulse[i]) / 2.0;
+    }
+    let total = step[len - 1];
+    step.iter().map(|v| v / total).collect()
+}
+
+fn step_at(step: &[f64], x: f64) -> f64 {
+    let pos = (x + STEP_HALF_WIDTH as f64) * STEP_PHASES as f64;
+    if pos <= 0.0 {
+        return 0.0;
+    }
+    let idx = pos.floor() as usize;
+    if idx + 1 >= step.len() {
+        return 1.0;
+    }
+    let t = pos - idx as f64;
+    step[idx] + (step[idx + 1] - step[idx]) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::CLOCK_FREQ_PAL;
+    use super::*;
+
+    fn sample(data: Vec<i8>, repeat_offset: u32, repeat_length: u32) -> Sample {
+        Sample {
+            name: String::new(),
+            finetune: 0,
+            length: data.len() as u32,
+            volume: 64,
+            repeat_offset,
+            repeat_length,
+            data,
+        }
+    }
+
+    fn channel(period: u16, sample: &Sample) -> ChannelState {
+        let mut channel = ChannelState::default();
+        channel.sample_no = Some(1);
+        channel.sample_end = sample.length;
+        channel.output_period = period;
+        channel.output_volume = 64;
+        channel
+    }
+
+    #[test]
+    fn test_one_shot_word() {
+        // after the end a one-shot sample plays its first word forever
+        let sample = sample(vec![40, 40, 1, 2, 3, 4, 5, 6], 0, 2);
+        let mut channel = channel(428, &sample);
+        let mut paula = Paula::new(1, CLOCK_FREQ_PAL, 8000, Mixer::Paula { dma_limit: false });
+        let values: Vec<_> = (0..100)
+            .map(|_| paula.next_sample(0, &mut channel, Some((&sample, &sample.data))))
+            .collect();
+        assert!(channel.in_loop);
+        assert!(channel.offset < 2.0);
+        for value in &values[80..] {
+            assert!((value - 40.0 * 64.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_min_period() {
+        // 100 samples at 44.1 kHz are ~8043 Paula clocks, the first byte is
+        // fetched immediately
+        let fetched = |mixer| {
+            let sample = sample(vec![0; 1000], 0, 2);
+            let mut channel = channel(100, &sample);
+            let mut paula = Paula::new(1, CLOCK_FREQ_PAL, 44100, mixer);
+            for _ in 0..100 {
+                paula.next_sample(0, &mut channel, Some((&sample, &sample.data)));
+            }
+            channel.offset as usize
+        };
+        assert_eq!(72, fetched(Mixer::Paula { dma_limit: false }));
+        assert_eq!(65, fetched(Mixer::Paula { dma_limit: true }));
+    }
+
+    #[test]
+    fn test_step() {
+        let step = step_table();
+        assert_eq!(0.0, step_at(&step, -20.0));
+        assert!((step_at(&step, 0.0) - 0.5).abs() < 1e-9);
+        assert_eq!(1.0, step_at(&step, 20.0));
+
+        // a step is delayed by the half width and reaches its level exactly
+        let mut voice = Voice::default();
+        voice.set_level(&step, 0.0, 100.0);
+        let values: Vec<_> = (0..40).map(|_| voice.next()).collect();
+        assert!(values[0].abs() < 0.1);
+        assert!((values[STEP_HALF_WIDTH - 1] - 50.0).abs() < 1e-9);
+        assert!((values[39] - 100.0).abs() < 1e-9);
+    }
+}